@@ -0,0 +1,280 @@
+use std::fmt::Write;
+
+use amcx_core::Model;
+use gltf::json::Root;
+use nalgebra::UnitQuaternion;
+
+use crate::{
+    calibration::Calibration,
+    euler::EulerOrder,
+    mapping::Mapping,
+    to_gltf::{
+        ConvertOptions, ConvertingError, ConvertingWarning, Joint, Motion, NodeTree, get_node,
+        sample_motion,
+    },
+};
+
+/// Decomposition of the joint rotations into anatomical angles, with optional orders for single joints
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AngleOptions {
+    /// Order of the joints that are not listed
+    pub order: EulerOrder,
+    pub joints: Vec<(Joint, EulerOrder)>,
+}
+impl AngleOptions {
+    pub fn order(&self, joint: &str) -> EulerOrder {
+        self.joints
+            .iter()
+            .find_map(|(j, order)| (j == joint).then_some(*order))
+            .unwrap_or(self.order)
+    }
+
+    pub fn set(&mut self, joint: Joint, order: EulerOrder) {
+        match self.joints.iter_mut().find(|(j, _)| *j == joint) {
+            Some((_, old)) => *old = order,
+            None => self.joints.push((joint, order)),
+        }
+    }
+}
+
+/// Angles of every joint relative to its rest pose
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AngleReport {
+    /// Keyframe times in seconds
+    pub times: Vec<f32>,
+    /// Joints parents first
+    pub joints: Vec<JointAngles>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointAngles {
+    pub joint: Joint,
+    pub order: EulerOrder,
+    /// Angles in degrees at every keyframe, in the order of composition
+    pub angles: Vec<[f32; 3]>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AngleStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub std_dev: f32,
+}
+impl AngleStats {
+    pub fn range(&self) -> f32 {
+        self.max - self.min
+    }
+}
+
+impl JointAngles {
+    /// Angles of a single axis, in the order of composition
+    pub fn axis(&self, axis: usize) -> impl Iterator<Item = f32> + '_ {
+        self.angles.iter().map(move |angles| angles[axis])
+    }
+
+    /// Statistics of every axis, in the order of composition
+    pub fn stats(&self) -> [AngleStats; 3] {
+        [0, 1, 2].map(|axis| {
+            let count = self.angles.len().max(1) as f32;
+            let (min, max) = self
+                .axis(axis)
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), a| {
+                    (min.min(a), max.max(a))
+                });
+            let mean = self.axis(axis).sum::<f32>() / count;
+            let variance = self.axis(axis).map(|a| (a - mean).powi(2)).sum::<f32>() / count;
+            match self.angles.is_empty() {
+                true => AngleStats::default(),
+                false => AngleStats {
+                    min,
+                    max,
+                    mean,
+                    std_dev: variance.sqrt(),
+                },
+            }
+        })
+    }
+
+    /// Names of the axes in the order of composition, like `['Z', 'X', 'Y']`
+    pub fn axis_names(&self) -> [char; 3] {
+        self.order.axes().map(|axis| ['X', 'Y', 'Z'][axis])
+    }
+}
+
+impl AngleReport {
+    /// Angles of a single joint around the named axis
+    pub fn series(&self, joint: &str, axis: char) -> Option<Vec<f32>> {
+        let joint = self.joints.iter().find(|j| j.joint == joint)?;
+        let axis = joint.axis_names().iter().position(|a| *a == axis)?;
+        Some(joint.axis(axis).collect())
+    }
+
+    /// Time series with one column per joint axis
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("Time");
+        for joint in &self.joints {
+            for axis in joint.axis_names() {
+                write!(csv, ",{}", field(&format!("{} {axis}", joint.joint))).unwrap();
+            }
+        }
+        csv.push('\n');
+
+        for (frame, time) in self.times.iter().enumerate() {
+            write!(csv, "{time:.4}").unwrap();
+            for joint in &self.joints {
+                let angles = joint.angles.get(frame).copied().unwrap_or_default();
+                for angle in angles {
+                    write!(csv, ",{angle:.3}").unwrap();
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Statistics with one row per joint axis
+    pub fn summary_csv(&self) -> String {
+        let mut csv = String::from("Joint,Order,Axis,Min,Max,Range,Mean,StdDev\n");
+        for joint in &self.joints {
+            for (axis, stats) in joint.axis_names().into_iter().zip(joint.stats()) {
+                writeln!(
+                    csv,
+                    "{},{},{axis},{:.3},{:.3},{:.3},{:.3},{:.3}",
+                    field(&joint.joint),
+                    joint.order,
+                    stats.min,
+                    stats.max,
+                    stats.range(),
+                    stats.mean,
+                    stats.std_dev
+                )
+                .unwrap();
+            }
+        }
+        csv
+    }
+}
+
+/// Breaks the local joint rotations into angles relative to the rest pose of the target model,
+/// or of the source skeleton when retargeting
+pub fn convert(
+    gltf_model: &Root,
+    amcx_model: &Model,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    angle_options: &AngleOptions,
+) -> Result<(AngleReport, Vec<ConvertingWarning>), ConvertingError> {
+    let source_options;
+    let (gltf_model, options) = match &options.retarget {
+        Some(retarget) => {
+            source_options = retarget.source_options(options);
+            (&retarget.source, &source_options)
+        }
+        None => (gltf_model, options),
+    };
+    let mut warnings = Vec::new();
+    let Motion {
+        times, rotations, ..
+    } = sample_motion(
+        amcx_model,
+        gltf_model,
+        calibration,
+        mapping,
+        options,
+        &mut warnings,
+    )?;
+
+    let joints = options.rig.joints(gltf_model)?;
+    let mut order = Vec::new();
+    for tree_root in NodeTree::new(gltf_model, &joints)? {
+        tree_root.for_each_bf(&mut |node| order.push(node.index));
+    }
+
+    let mut report = AngleReport {
+        times,
+        joints: Vec::with_capacity(order.len()),
+    };
+    for index in order {
+        let node = get_node(gltf_model, index)?;
+        let joint = node
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Node{}", index.value()));
+        let rest_inv = node
+            .rotation
+            .map_or(UnitQuaternion::identity(), |r| {
+                UnitQuaternion::from_quaternion(r.0.into())
+            })
+            .inverse();
+        let euler_order = angle_options.order(&joint);
+        let angles = rotations
+            .get(&index)
+            .map(|track| {
+                track
+                    .iter()
+                    .map(|q| euler_order.decompose(&(rest_inv * q)).map(f32::to_degrees))
+                    .collect()
+            })
+            .unwrap_or_else(|| vec![[0.0; 3]; report.times.len()]);
+        report.joints.push(JointAngles {
+            joint,
+            order: euler_order,
+            angles,
+        });
+    }
+    Ok((report, warnings))
+}
+
+/// Quotes CSV fields that would break the row
+fn field(value: &str) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{recording, rig};
+
+    #[test]
+    fn joint_angle_report() {
+        let mut angle_options = AngleOptions::default();
+        angle_options.set("Upper".into(), EulerOrder::Xyz);
+        let (report, _) = convert(
+            &rig(),
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &ConvertOptions::default(),
+            &angle_options,
+        )
+        .unwrap();
+        assert_eq!(report.joints.len(), 2);
+        assert_eq!(report.joints[0].order, EulerOrder::Xyz);
+        assert_eq!(report.joints[1].order, EulerOrder::Zxy);
+
+        // the upper joint turns around x at 0.1 rad/s until the last sample
+        let duration = recording()[0].1.last().unwrap().timestamp.as_secs_f32();
+        let [flexion, ..] = report.joints[0].stats();
+        assert!(
+            (flexion.max - (0.1 * duration).to_degrees()).abs() < 0.05,
+            "{flexion:?}"
+        );
+        assert!(flexion.min.abs() < 0.1 && flexion.range() > 5.0);
+
+        let csv = report.to_csv();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("Time,Upper X,Upper Y,Upper Z,Lower Z,Lower X,Lower Y")
+        );
+        assert_eq!(lines.count(), 100);
+        let summary = report.summary_csv();
+        assert_eq!(summary.lines().count(), 1 + 2 * 3);
+        assert!(summary.lines().nth(1).unwrap().starts_with("Upper,XYZ,X,"));
+    }
+}
@@ -0,0 +1,599 @@
+use std::{collections::HashMap, fmt::Display};
+
+use amcx_core::{Model, Sensor};
+use nalgebra::{Matrix3, Matrix4, Rotation3, Unit, UnitQuaternion, Vector3, Vector4};
+
+use crate::to_gltf::{ConvertingError, process_ahrs};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CalibrationMethod {
+    /// The first 0.5 s of the recording are stationary,
+    /// the last AHRS rotation axis is used as "z".
+    #[default]
+    Stationary,
+    /// The subject holds the target skeleton's rest pose (T-pose or N-pose)
+    /// for the first second of the recording, the rig supplies the segment orientations.
+    Pose,
+}
+impl CalibrationMethod {
+    pub const ALL: [CalibrationMethod; 2] =
+        [CalibrationMethod::Stationary, CalibrationMethod::Pose];
+}
+impl Display for CalibrationMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationMethod::Stationary => "Stationary".fmt(f),
+            CalibrationMethod::Pose => "Pose".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Calibration<'a> {
+    pub reference: Option<&'a Model>,
+    pub method: CalibrationMethod,
+    pub hinges: Vec<HingeCalibration<'a>>,
+}
+
+/// Functional calibration of a hinge joint (elbow, knee)
+/// from a flexion/extension movement.
+#[derive(Debug, Clone)]
+pub struct HingeCalibration<'a> {
+    pub recording: &'a Model,
+    pub parent: Sensor,
+    pub child: Sensor,
+    /// Hinge axis in the child joint's local frame, either sign
+    pub axis: Unit<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HingeAxes {
+    pub parent: Unit<Vector3<f32>>,
+    pub child: Unit<Vector3<f32>>,
+}
+
+impl HingeCalibration<'_> {
+    const MIN_ANGULAR_SPEED: f32 = 0.2; // rad/s
+    const MIN_SAMPLES: usize = 50;
+    const ITERATIONS: usize = 30;
+
+    /// Estimates the joint axis in both sensor frames.
+    ///
+    /// For a hinge the angular velocities of both segments differ only along the axis,
+    /// so their components orthogonal to it have equal magnitude:
+    /// `|gyr_parent x j_parent| = |gyr_child x j_child|`.
+    /// The axes are fitted with Gauss-Newton in spherical coordinates.
+    /// Signs are chosen so that rotations of both segments around the axis correlate.
+    pub fn estimate_axes(&self) -> Result<HingeAxes, ConvertingError> {
+        let stream = |sensor: &Sensor| {
+            self.recording
+                .iter()
+                .find(|(s, _)| s == sensor)
+                .map(|(_, stream)| stream)
+                .ok_or(ConvertingError::HingeSensorMissing(sensor.clone()))
+        };
+        let parent = stream(&self.parent)?;
+        let child = stream(&self.child)?;
+
+        let samples: Vec<(Vector3<f32>, Vector3<f32>)> = parent
+            .iter()
+            .zip(child.iter())
+            .map(|(p, c)| (Vector3::from(p.sample.gyr), Vector3::from(c.sample.gyr)))
+            .filter(|(p, c)| p.norm().max(c.norm()) > Self::MIN_ANGULAR_SPEED)
+            .collect();
+        let undetermined =
+            || ConvertingError::HingeAxisUndetermined(self.parent.clone(), self.child.clone());
+        if samples.len() < Self::MIN_SAMPLES {
+            return Err(undetermined());
+        }
+
+        // initial guess: dominant rotation axis of each segment
+        let (phi_p, theta_p) = to_spherical(&dominant_axis(samples.iter().map(|s| s.0)));
+        let (phi_c, theta_c) = to_spherical(&dominant_axis(samples.iter().map(|s| s.1)));
+        let mut params = Vector4::new(phi_p, theta_p, phi_c, theta_c);
+
+        for _ in 0..Self::ITERATIONS {
+            let (j_p, dj_p) = from_spherical(params[0], params[1]);
+            let (j_c, dj_c) = from_spherical(params[2], params[3]);
+
+            let mut jtj = Matrix4::zeros();
+            let mut jte = Vector4::zeros();
+            for (g_p, g_c) in &samples {
+                let (n_p, d_p) = orthogonal_norm(g_p, &j_p);
+                let (n_c, d_c) = orthogonal_norm(g_c, &j_c);
+                let error = n_p - n_c;
+                let jacobian = Vector4::new(
+                    d_p.dot(&dj_p.0),
+                    d_p.dot(&dj_p.1),
+                    -d_c.dot(&dj_c.0),
+                    -d_c.dot(&dj_c.1),
+                );
+                jtj += jacobian * jacobian.transpose();
+                jte += jacobian * error;
+            }
+            let Some(step) = jtj.try_inverse().map(|inv| inv * jte) else {
+                break;
+            };
+            params -= step;
+            if step.norm() < 1e-6 {
+                break;
+            }
+        }
+
+        let j_p = from_spherical(params[0], params[1]).0;
+        let mut j_c = from_spherical(params[2], params[3]).0;
+        let correlation: f32 = samples
+            .iter()
+            .map(|(g_p, g_c)| g_p.dot(&j_p) * g_c.dot(&j_c))
+            .sum();
+        if correlation < 0.0 {
+            j_c = -j_c;
+        }
+
+        match (Unit::try_new(j_p, 1e-6), Unit::try_new(j_c, 1e-6)) {
+            (Some(parent), Some(child)) => Ok(HingeAxes { parent, child }),
+            _ => Err(undetermined()),
+        }
+    }
+}
+
+/// Principal direction of the angular velocity
+fn dominant_axis(gyr: impl Iterator<Item = Vector3<f32>>) -> Vector3<f32> {
+    let covariance: Matrix3<f32> = gyr.map(|g| g * g.transpose()).sum();
+    let eigen = covariance.symmetric_eigen();
+    eigen
+        .eigenvectors
+        .column(eigen.eigenvalues.imax())
+        .into_owned()
+}
+
+fn to_spherical(v: &Vector3<f32>) -> (f32, f32) {
+    let v = v.normalize();
+    (v.z.clamp(-1.0, 1.0).asin(), v.y.atan2(v.x))
+}
+
+/// Unit vector with its derivatives by `phi` and `theta`
+fn from_spherical(phi: f32, theta: f32) -> (Vector3<f32>, (Vector3<f32>, Vector3<f32>)) {
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    let j = Vector3::new(cos_phi * cos_theta, cos_phi * sin_theta, sin_phi);
+    let dj_dphi = Vector3::new(-sin_phi * cos_theta, -sin_phi * sin_theta, cos_phi);
+    let dj_dtheta = Vector3::new(-cos_phi * sin_theta, cos_phi * cos_theta, 0.0);
+    (j, (dj_dphi, dj_dtheta))
+}
+
+/// `|g x j|` with its gradient by `j`
+fn orthogonal_norm(g: &Vector3<f32>, j: &Vector3<f32>) -> (f32, Vector3<f32>) {
+    let cross = g.cross(j);
+    let norm = cross.norm();
+    if norm < f32::EPSILON {
+        return (norm, Vector3::zeros());
+    }
+    (norm, cross.cross(g) / norm)
+}
+
+pub(crate) struct Calibrator {
+    q_left: UnitQuaternion<f32>,
+    q_right: UnitQuaternion<f32>,
+}
+impl Default for Calibrator {
+    fn default() -> Self {
+        Calibrator {
+            q_left: UnitQuaternion::identity(),
+            q_right: UnitQuaternion::identity(),
+        }
+    }
+}
+
+impl Calibrator {
+    /// `rest` gives the rest orientation of the joint a sensor is coupled to,
+    /// in the AHRS world frame.
+    pub(crate) fn new(
+        reference: Option<&Model>,
+        method: CalibrationMethod,
+        rest: impl Fn(&str) -> UnitQuaternion<f32>,
+    ) -> Result<HashMap<String, Calibrator>, ConvertingError> {
+        match reference {
+            Some(reference) => match method {
+                CalibrationMethod::Stationary => Calibrator::stationary(reference),
+                CalibrationMethod::Pose => Calibrator::pose(reference, rest),
+            },
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    fn stationary(reference_model: &Model) -> Result<HashMap<String, Calibrator>, ConvertingError> {
+        let mut calibrators = HashMap::new();
+
+        for (sensor, stream) in reference_model {
+            // get "down"
+            let stationary_time = 0.5;
+            let approx_y: Vector3<f32> = stream
+                .iter()
+                .map_while(|r| {
+                    (r.timestamp.as_secs_f32() < stationary_time)
+                        .then_some(Vector3::from(r.sample.acc))
+                })
+                .sum();
+            let approx_y = -approx_y
+                .try_normalize(f32::EPSILON)
+                .ok_or_else(|| ConvertingError::CalibrationNotStationary(sensor.clone()))?;
+            // get rotation -> axis
+            let new_z = process_ahrs(sensor, stream)?
+                .last()
+                .ok_or_else(|| ConvertingError::NoSamples(sensor.clone()))?
+                .axis()
+                .ok_or_else(|| ConvertingError::CalibrationStatic(sensor.clone()))?
+                .into_inner(); // already normalized
+            // crossproduct third axis
+            let new_x = new_z
+                .cross(&approx_y)
+                .try_normalize(f32::EPSILON)
+                .ok_or_else(|| ConvertingError::CalibrationDegenerate(sensor.clone()))?;
+            // orthogonalize and normalize
+            let new_y = new_z.cross(&new_x).normalize();
+
+            // convert to quaternion
+            let rotation_matrix =
+                Rotation3::from_matrix(&Matrix3::from_columns(&[new_x, new_y, new_z]));
+            let quat = UnitQuaternion::from_rotation_matrix(&rotation_matrix);
+
+            calibrators.insert(
+                sensor.into(),
+                Calibrator {
+                    q_left: quat.inverse(),
+                    q_right: quat,
+                },
+            );
+        }
+        Ok(calibrators)
+    }
+
+    /// Sensor-to-segment rotations for every sensor at once.
+    ///
+    /// While the pose is held each segment is in its rest orientation,
+    /// so the sensor orientation is the segment's one times its mounting.
+    /// Gravity fixes the mounting only up to a turn around the vertical:
+    /// the sensor is assumed to be mounted with the smallest rotation
+    /// that explains its tilt, a mounting turned around the segment's vertical
+    /// shows up as a heading offset of the segment.
+    /// The recording is expected to start in the same pose.
+    fn pose(
+        reference_model: &Model,
+        rest: impl Fn(&str) -> UnitQuaternion<f32>,
+    ) -> Result<HashMap<String, Calibrator>, ConvertingError> {
+        let mut calibrators = HashMap::new();
+
+        for (sensor, stream) in reference_model {
+            // accelerometer points "up" while the pose is held
+            let pose_time = 1.0;
+            let approx_up: Vector3<f32> = stream
+                .iter()
+                .map_while(|r| {
+                    (r.timestamp.as_secs_f32() < pose_time).then_some(Vector3::from(r.sample.acc))
+                })
+                .sum();
+            if approx_up.norm() < f32::EPSILON {
+                return Err(ConvertingError::CalibrationNotStationary(sensor.clone()));
+            }
+            // segment frame -> world frame
+            let segment = rest(sensor);
+            // sensor frame -> segment frame, "up" of the segment in its own frame
+            let mounting =
+                UnitQuaternion::rotation_between(&approx_up, &(segment.inverse() * Vector3::z()))
+                    .unwrap_or_else(|| {
+                        // upside down, turn around any orthogonal axis
+                        let other = match approx_up.x.abs() < 0.9 * approx_up.norm() {
+                            true => Vector3::x(),
+                            false => Vector3::y(),
+                        };
+                        let orthogonal = Unit::new_normalize(approx_up.cross(&other));
+                        UnitQuaternion::from_axis_angle(&orthogonal, std::f32::consts::PI)
+                    });
+            // sensor frame -> world frame while the pose is held
+            let q_pose = segment * mounting;
+
+            calibrators.insert(
+                sensor.into(),
+                Calibrator {
+                    q_left: q_pose,
+                    q_right: q_pose.inverse(),
+                },
+            );
+        }
+        Ok(calibrators)
+    }
+
+    /// Rotates the sensor frame so that `sensor_axis` matches the joint axis,
+    /// keeping the calibrated orientation at the start of the recording.
+    pub(crate) fn align_axis(
+        &mut self,
+        sensor_axis: &Unit<Vector3<f32>>,
+        world_axis: &Unit<Vector3<f32>>,
+    ) {
+        let expected = self.sensor_axis(world_axis);
+        let correction = UnitQuaternion::rotation_between_axis(&expected, sensor_axis)
+            .unwrap_or_else(|| {
+                // opposite axes, turn around any orthogonal one
+                let other = match expected.x.abs() < 0.9 {
+                    true => Vector3::x(),
+                    false => Vector3::y(),
+                };
+                let orthogonal = Unit::new_normalize(expected.cross(&other));
+                UnitQuaternion::from_axis_angle(&orthogonal, std::f32::consts::PI)
+            });
+        self.q_left *= correction.inverse();
+        self.q_right = correction * self.q_right;
+    }
+
+    /// Joint axis as currently expected in the sensor frame
+    pub(crate) fn sensor_axis(&self, world_axis: &Unit<Vector3<f32>>) -> Unit<Vector3<f32>> {
+        self.q_right * world_axis
+    }
+
+    pub(crate) fn calibrate(&self, q: UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+        self.q_left * q * self.q_right
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use amcx_core::{Record, Sample};
+    use gltf::json::Index;
+
+    use super::*;
+    use crate::{
+        fixtures::{recording, rig, stream, try_convert_with},
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, calculate_rotations},
+    };
+
+    #[test]
+    fn static_calibration() {
+        let reference = vec![("Upper".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3]))];
+        let calibration = Calibration {
+            reference: Some(&reference),
+            method: CalibrationMethod::Stationary,
+            ..Default::default()
+        };
+        let result = try_convert_with(
+            rig(),
+            &recording(),
+            &calibration,
+            &ConvertOptions::default(),
+        );
+        assert!(
+            matches!(result, Err(ConvertingError::CalibrationStatic(sensor)) if sensor == "Upper")
+        );
+    }
+
+    #[test]
+    fn degenerate_calibration() {
+        // turning about the vertical gives no second axis
+        let reference = vec![(
+            "Upper".into(),
+            stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, 0.1]),
+        )];
+        let calibration = Calibration {
+            reference: Some(&reference),
+            method: CalibrationMethod::Stationary,
+            ..Default::default()
+        };
+        let result = try_convert_with(
+            rig(),
+            &recording(),
+            &calibration,
+            &ConvertOptions::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ConvertingError::CalibrationDegenerate(sensor)) if sensor == "Upper"
+        ));
+    }
+
+    #[test]
+    fn pose_calibration() {
+        // rest orientations of the rig in the T-pose, sensors tilted on their segments
+        let rests = [
+            UnitQuaternion::identity(),
+            UnitQuaternion::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_2),
+        ];
+        let mountings = [
+            UnitQuaternion::from_euler_angles(0.3, 0.0, 0.0),
+            UnitQuaternion::from_euler_angles(0.0, -0.5, 0.0),
+        ];
+        let sensors = ["Upper", "Lower"];
+
+        let mut reference = Vec::new();
+        let mut model = Vec::new();
+        for ((sensor, rest), mounting) in sensors.into_iter().zip(rests).zip(mountings) {
+            let q_pose = rest * mounting;
+            let up = q_pose.inverse() * Vector3::z();
+            // both segments turn around the world x axis
+            let gyr = q_pose.inverse() * Vector3::x() * 0.5;
+            reference.push((sensor.into(), stream(100, up.into(), [0.0; 3])));
+            model.push((sensor.into(), stream(100, up.into(), gyr.into())));
+        }
+        let calibration = Calibration {
+            reference: Some(&reference),
+            method: CalibrationMethod::Pose,
+            ..Default::default()
+        };
+        let rotations = calculate_rotations(
+            &model,
+            &rig(),
+            &calibration,
+            &Mapping::default(),
+            &ConvertOptions::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let upper = rotations[&Index::new(0)].last().unwrap();
+        assert!(upper.angle() > 0.4);
+        assert!((upper.axis().unwrap().into_inner() - Vector3::x()).norm() < 1e-3);
+        // the segments keep their relative rest orientation
+        for lower in &rotations[&Index::new(1)] {
+            assert!(lower.angle_to(&rests[1]) < 1e-3, "{lower:?}");
+        }
+    }
+
+    /// Gyroscope samples of two segments joined by a hinge around `axis` in the parent frame,
+    /// `mounting` turns the parent sensor frame into the child one at zero flexion.
+    fn hinge_recording(axis: Vector3<f32>, mounting: UnitQuaternion<f32>) -> Model {
+        let axis = Unit::new_normalize(axis);
+        let (parent, child) = (0..300)
+            .map(|i| {
+                let t = i as f32 * 0.01;
+                let gyr_parent = Vector3::new(0.5 * (1.3 * t).sin(), 0.4 * (0.7 * t).cos(), 0.3)
+                    + axis.into_inner() * 0.6 * (0.9 * t).sin();
+                let flexion = 0.8 * (2.0 * t).sin();
+                let flexion_speed = 1.6 * (2.0 * t).cos();
+                let child_frame = UnitQuaternion::from_axis_angle(&axis, flexion) * mounting;
+                let gyr_child =
+                    child_frame.inverse() * (gyr_parent + axis.into_inner() * flexion_speed);
+                let record = |gyr: Vector3<f32>| Record {
+                    timestamp: Duration::from_millis(10 * i as u64),
+                    sample: Sample {
+                        acc: [0.0, 0.0, 1.0],
+                        gyr: gyr.into(),
+                    },
+                };
+                (record(gyr_parent), record(gyr_child))
+            })
+            .unzip();
+        vec![("Upper".into(), parent), ("Lower".into(), child)]
+    }
+
+    #[test]
+    fn hinge_axes() {
+        let axis = Vector3::new(1.0, 2.0, 0.5).normalize();
+        let mounting = UnitQuaternion::from_euler_angles(0.4, -1.1, 2.0);
+        let recording = hinge_recording(axis, mounting);
+        let hinge = HingeCalibration {
+            recording: &recording,
+            parent: "Upper".into(),
+            child: "Lower".into(),
+            axis: Vector3::x_axis(),
+        };
+        let axes = hinge.estimate_axes().unwrap();
+        // either sign fits, but both axes have to agree on it
+        let sign = axes.parent.dot(&axis).signum();
+        assert!(
+            (axes.parent.into_inner() - sign * axis).norm() < 1e-2,
+            "{axes:?}"
+        );
+        let child_axis = mounting.inverse() * axis;
+        assert!(
+            (axes.child.into_inner() - sign * child_axis).norm() < 1e-2,
+            "{axes:?}"
+        );
+
+        let still = vec![
+            ("Upper".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+            ("Lower".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+        ];
+        let hinge = HingeCalibration {
+            recording: &still,
+            ..hinge
+        };
+        assert!(matches!(
+            hinge.estimate_axes(),
+            Err(ConvertingError::HingeAxisUndetermined(parent, child))
+                if parent == "Upper" && child == "Lower"
+        ));
+    }
+
+    #[test]
+    fn align_axis() {
+        let world_axis = Vector3::z_axis();
+        for sensor_axis in [Vector3::y_axis(), -Vector3::z_axis(), Vector3::z_axis()] {
+            let mut calibrator = Calibrator::default();
+            calibrator.align_axis(&sensor_axis, &world_axis);
+            // the start of the recording stays at rest
+            let start = calibrator.calibrate(UnitQuaternion::identity());
+            assert!(start.angle() < 1e-5);
+            // turning around the sensor axis turns around the joint axis
+            let q = calibrator.calibrate(UnitQuaternion::from_axis_angle(&sensor_axis, 0.7));
+            let expected = UnitQuaternion::from_axis_angle(&world_axis, 0.7);
+            assert!(q.angle_to(&expected) < 1e-5, "{sensor_axis:?}: {q:?}");
+        }
+    }
+
+    #[test]
+    fn convert_with_hinge_calibration() {
+        // the upper segment rests in the world frame, the child sensor sits askew on the lower one
+        let hinge_axis = Vector3::new(1.0, 2.0, 0.5).normalize();
+        let mounting = UnitQuaternion::from_euler_angles(0.4, -0.6, 0.5);
+        let hinge_recording = hinge_recording(hinge_axis, mounting);
+        // the upper segment rests, the lower one flexes
+        let flexion = mounting.inverse() * hinge_axis * 0.5;
+        let model = vec![
+            ("Upper".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+            ("Lower".into(), stream(100, [0.0, 0.0, 1.0], flexion.into())),
+        ];
+        let rest = UnitQuaternion::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_2);
+        let flexion_axis = |hinges| {
+            let calibration = Calibration {
+                hinges,
+                ..Default::default()
+            };
+            let rotations = calculate_rotations(
+                &model,
+                &rig(),
+                &calibration,
+                &Mapping::default(),
+                &ConvertOptions::default(),
+                &mut Vec::new(),
+            )
+            .unwrap();
+            let lower = rotations[&Index::new(1)].last().unwrap() * rest.inverse();
+            lower.axis().unwrap().into_inner()
+        };
+
+        assert!((flexion_axis(Vec::new()) - hinge_axis).norm() > 0.1);
+        // the sign of the joint axis doesn't matter
+        let joint_axis = Unit::new_normalize(rest.inverse() * hinge_axis);
+        for axis in [joint_axis, -joint_axis] {
+            let hinge = HingeCalibration {
+                recording: &hinge_recording,
+                parent: "Upper".into(),
+                child: "Lower".into(),
+                axis,
+            };
+            let axis = flexion_axis(vec![hinge]);
+            assert!((axis - hinge_axis).norm() < 1e-2, "{axis:?}");
+        }
+    }
+
+    #[test]
+    fn calibration_without_stationary_period() {
+        let mut reference = vec![(
+            "Upper".into(),
+            stream(100, [0.0, 0.0, 1.0], [0.1, 0.0, 0.0]),
+        )];
+        reference[0]
+            .1
+            .iter_mut()
+            .for_each(|record| record.timestamp += Duration::from_secs(2));
+        for method in CalibrationMethod::ALL {
+            let calibration = Calibration {
+                reference: Some(&reference),
+                method,
+                ..Default::default()
+            };
+            let result = try_convert_with(
+                rig(),
+                &recording(),
+                &calibration,
+                &ConvertOptions::default(),
+            );
+            assert!(matches!(
+                result,
+                Err(ConvertingError::CalibrationNotStationary(sensor)) if sensor == "Upper"
+            ));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::Display;
+
+use nalgebra::UnitQuaternion;
+
+use crate::to_gltf::Joint;
+
+/// Spreads the rotation between two measured joints over the unmeasured joints between them,
+/// like the segments of a spine between a pelvis and a chest sensor
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainRule {
+    /// Measured joint that ends the chain, the chain starts at its closest measured ancestor
+    pub end: Joint,
+    pub distribution: Distribution,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Distribution {
+    /// Every joint of the chain bends by the same amount
+    #[default]
+    Even,
+    /// Share of every joint from the start of the chain, the end joint included
+    Weights(Vec<f32>),
+}
+impl Distribution {
+    /// Cumulative share of the chain rotation at each of `count` unmeasured joints,
+    /// `None` if the weights don't fit the chain
+    pub fn shares(&self, count: usize) -> Option<Vec<f32>> {
+        let weights = match self {
+            Distribution::Even => vec![1.0; count + 1],
+            Distribution::Weights(weights) => weights.clone(),
+        };
+        let total: f32 = weights.iter().sum();
+        if weights.len() != count + 1 || weights.iter().any(|w| *w < 0.0) || total <= 0.0 {
+            return None;
+        }
+        let shares = weights[..count]
+            .iter()
+            .scan(0.0, |sum, w| {
+                *sum += w / total;
+                Some(*sum)
+            })
+            .collect();
+        Some(shares)
+    }
+}
+impl Display for Distribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Distribution::Even => write!(f, "Even"),
+            Distribution::Weights(weights) => {
+                let weights: Vec<_> = weights.iter().map(|w| w.to_string()).collect();
+                write!(f, "Weights {}", weights.join(" "))
+            }
+        }
+    }
+}
+
+/// Rotation of the chain start towards the chain end by `share`, along the shortest path
+pub fn partial(
+    start: &UnitQuaternion<f32>,
+    end: &UnitQuaternion<f32>,
+    share: f32,
+) -> UnitQuaternion<f32> {
+    let mut relative = start.inverse() * end;
+    if relative.w < 0.0 {
+        relative = UnitQuaternion::new_unchecked(-relative.into_inner());
+    }
+    start * relative.powf(share)
+}
+
+#[cfg(test)]
+mod tests {
+    use gltf::json::Root;
+
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        fixtures::stream,
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, ConvertingError, calculate_rotations},
+    };
+
+    const SPINE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "nodes": [
+            { "name": "Pelvis", "children": [1] },
+            { "name": "Spine", "children": [2] },
+            { "name": "Chest" }
+        ],
+        "skins": [{ "joints": [0, 1, 2] }]
+    }"#;
+
+    /// Local rotation angles of the spine and the chest at the last sample
+    fn bent_spine(chains: Vec<ChainRule>) -> Result<(f32, f32), ConvertingError> {
+        let root = Root::from_str(SPINE).unwrap();
+        let model = vec![
+            (
+                "Pelvis".into(),
+                stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, 0.0]),
+            ),
+            (
+                "Chest".into(),
+                stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, 0.4]),
+            ),
+        ];
+        let options = ConvertOptions {
+            chains,
+            ..Default::default()
+        };
+        let rotations = calculate_rotations(
+            &model,
+            &root,
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+            &mut Vec::new(),
+        )?;
+        let angle = |index: usize| {
+            let track = &rotations[&gltf::json::Index::new(index as u32)];
+            track.last().unwrap().angle()
+        };
+        Ok((angle(1), angle(2)))
+    }
+
+    #[test]
+    fn chain_distribution() {
+        let (spine, chest) = bent_spine(Vec::new()).unwrap();
+        assert!(spine < 1e-4 && chest > 0.3);
+
+        let even = ChainRule {
+            end: "Chest".into(),
+            distribution: Distribution::Even,
+        };
+        let (spine, chest) = bent_spine(vec![even.clone()]).unwrap();
+        assert!(
+            (spine - chest).abs() < 1e-3 && spine > 0.15,
+            "{spine} {chest}"
+        );
+
+        let weighted = ChainRule {
+            distribution: Distribution::Weights(vec![1.0, 3.0]),
+            ..even.clone()
+        };
+        let (spine, chest) = bent_spine(vec![weighted]).unwrap();
+        assert!((chest - 3.0 * spine).abs() < 1e-3, "{spine} {chest}");
+
+        let mismatched = ChainRule {
+            distribution: Distribution::Weights(vec![1.0, 1.0, 1.0]),
+            ..even.clone()
+        };
+        assert!(matches!(
+            bent_spine(vec![mismatched]),
+            Err(ConvertingError::ChainWeightsMismatch { count: 1, .. })
+        ));
+        let missing = ChainRule {
+            end: "Head".into(),
+            ..even.clone()
+        };
+        assert!(matches!(
+            bent_spine(vec![missing]),
+            Err(ConvertingError::JointNotFound(_))
+        ));
+        let unmeasured = ChainRule {
+            end: "Spine".into(),
+            ..even
+        };
+        assert!(matches!(
+            bent_spine(vec![unmeasured]),
+            Err(ConvertingError::ChainEndUnmeasured(joint)) if joint == "Spine"
+        ));
+    }
+}
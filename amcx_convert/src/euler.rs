@@ -0,0 +1,106 @@
+use std::{fmt::Display, str::FromStr};
+
+use nalgebra::{UnitQuaternion, Vector3};
+use thiserror::Error;
+
+/// Order of intrinsic rotations, `Zxy` composes as `Rz * Rx * Ry`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EulerOrder {
+    Xyz,
+    Xzy,
+    Yxz,
+    Yzx,
+    #[default]
+    Zxy,
+    Zyx,
+}
+impl EulerOrder {
+    pub const ALL: [EulerOrder; 6] = [
+        EulerOrder::Xyz,
+        EulerOrder::Xzy,
+        EulerOrder::Yxz,
+        EulerOrder::Yzx,
+        EulerOrder::Zxy,
+        EulerOrder::Zyx,
+    ];
+
+    /// Axis indices in the order of composition
+    pub fn axes(&self) -> [usize; 3] {
+        match self {
+            EulerOrder::Xyz => [0, 1, 2],
+            EulerOrder::Xzy => [0, 2, 1],
+            EulerOrder::Yxz => [1, 0, 2],
+            EulerOrder::Yzx => [1, 2, 0],
+            EulerOrder::Zxy => [2, 0, 1],
+            EulerOrder::Zyx => [2, 1, 0],
+        }
+    }
+
+    /// Angles in radians, in the order of composition
+    pub fn decompose(&self, q: &UnitQuaternion<f32>) -> [f32; 3] {
+        let [i, j, k] = self.axes();
+        // +1 for cyclic orders (xyz, yzx, zxy), -1 otherwise
+        let s = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+        let r = q.to_rotation_matrix();
+        let r = r.matrix();
+
+        let b = (s * r[(i, k)]).clamp(-1.0, 1.0).asin();
+        let a = f32::atan2(-s * r[(j, k)], r[(k, k)]);
+        let c = f32::atan2(-s * r[(i, j)], r[(i, i)]);
+        [a, b, c]
+    }
+
+    /// Inverse of [`EulerOrder::decompose`]
+    pub fn compose(&self, angles: [f32; 3]) -> UnitQuaternion<f32> {
+        self.axes()
+            .into_iter()
+            .zip(angles)
+            .map(|(axis, angle)| {
+                let mut v = Vector3::zeros();
+                v[axis] = angle;
+                UnitQuaternion::from_scaled_axis(v)
+            })
+            .fold(UnitQuaternion::identity(), |acc, q| acc * q)
+    }
+}
+impl Display for EulerOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c] = self.axes().map(|axis| ['X', 'Y', 'Z'][axis]);
+        write!(f, "{a}{b}{c}")
+    }
+}
+
+impl FromStr for EulerOrder {
+    type Err = EulerOrderError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        EulerOrder::ALL
+            .into_iter()
+            .find(|order| order.to_string().eq_ignore_ascii_case(source))
+            .ok_or_else(|| EulerOrderError(source.into()))
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid rotation order {0}, expected one like \"ZXY\"")]
+pub struct EulerOrderError(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn euler_round_trip() {
+        let q = UnitQuaternion::from_euler_angles(0.3, -0.7, 1.2);
+        for order in EulerOrder::ALL {
+            let angles = order.decompose(&q);
+            assert!(order.compose(angles).angle_to(&q) < 1e-4, "{order}");
+            assert_eq!(order.to_string().to_lowercase().parse(), Ok(order));
+        }
+        let angles = EulerOrder::Zxy.decompose(&EulerOrder::Zxy.compose([0.1, 0.2, 0.3]));
+        for (angle, expected) in angles.into_iter().zip([0.1, 0.2, 0.3]) {
+            assert!((angle - expected).abs() < 1e-5);
+        }
+        assert!("XYX".parse::<EulerOrder>().is_err());
+    }
+}
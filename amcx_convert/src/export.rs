@@ -0,0 +1,214 @@
+use std::{borrow::Cow, fmt::Display};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use gltf::{
+    binary::{Glb, Header},
+    json::{Buffer, Root},
+};
+use thiserror::Error;
+
+/// How the converted model and its buffers are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// `.gltf` with the buffers as `.bin` files next to it
+    #[default]
+    Separate,
+    /// `.gltf` with the buffers as base64 data URIs
+    Embedded,
+    /// `.glb` with the JSON and all buffers in one binary file
+    Binary,
+}
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [
+        ExportFormat::Separate,
+        ExportFormat::Embedded,
+        ExportFormat::Binary,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Separate | ExportFormat::Embedded => "gltf",
+            ExportFormat::Binary => "glb",
+        }
+    }
+}
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Separate => write!(f, "glTF + bin"),
+            ExportFormat::Embedded => write!(f, "glTF embedded"),
+            ExportFormat::Binary => write!(f, "GLB"),
+        }
+    }
+}
+
+const DATA_URI: &str = "data:application/octet-stream;base64,";
+
+/// Points the buffer named `old` to `new`, so that exports into one folder don't overwrite each other
+pub fn rename_buffer(root: &mut Root, bins: &mut [(String, Vec<u8>)], old: &str, new: &str) {
+    for buffer in &mut root.buffers {
+        if buffer.uri.as_deref() == Some(old) {
+            buffer.uri = Some(new.into());
+        }
+    }
+    for (name, _) in bins {
+        if name == old {
+            *name = new.into();
+        }
+    }
+}
+
+/// Replaces the buffer URIs with base64 data URIs
+pub fn embed(root: &mut Root, bins: &[(String, Vec<u8>)]) -> Result<(), ExportError> {
+    for (i, buffer) in root.buffers.iter_mut().enumerate() {
+        let data = buffer_data(i, buffer, bins)?;
+        buffer.uri = Some(format!("{DATA_URI}{}", STANDARD.encode(data)));
+    }
+    Ok(())
+}
+
+/// Packs the model and all of its buffers into a single GLB
+pub fn to_glb(mut root: Root, bins: &[(String, Vec<u8>)]) -> Result<Vec<u8>, ExportError> {
+    let mut bin = Vec::new();
+    let mut offsets = Vec::with_capacity(root.buffers.len());
+    for (i, buffer) in root.buffers.iter().enumerate() {
+        // accessors rely on the buffer start being aligned to their component size
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        offsets.push(bin.len() as u64);
+        bin.extend_from_slice(&buffer_data(i, buffer, bins)?);
+    }
+
+    for view in &mut root.buffer_views {
+        let offset = offsets
+            .get(view.buffer.value())
+            .ok_or(ExportError::BufferMissing(view.buffer.value()))?;
+        let byte_offset = view.byte_offset.map_or(0, |offset| offset.0);
+        view.byte_offset = Some((offset + byte_offset).into());
+        view.buffer = gltf::json::Index::new(0);
+    }
+    root.buffers = match bin.is_empty() {
+        true => Vec::new(),
+        false => vec![Buffer {
+            byte_length: bin.len().into(),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        }],
+    };
+
+    let glb = Glb {
+        header: Header {
+            magic: *b"glTF",
+            version: 2,
+            length: 0, // calculated on write
+        },
+        json: Cow::Owned(root.to_vec()?),
+        bin: (!bin.is_empty()).then_some(Cow::Owned(bin)),
+    };
+    Ok(glb.to_vec()?)
+}
+
+fn buffer_data<'a>(
+    index: usize,
+    buffer: &Buffer,
+    bins: &'a [(String, Vec<u8>)],
+) -> Result<Cow<'a, [u8]>, ExportError> {
+    let uri = buffer
+        .uri
+        .as_deref()
+        .ok_or(ExportError::BufferMissing(index))?;
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, data) = data
+            .split_once(";base64,")
+            .ok_or(ExportError::InvalidDataUri(index))?;
+        let data = STANDARD
+            .decode(data)
+            .map_err(|_| ExportError::InvalidDataUri(index))?;
+        return Ok(Cow::Owned(data));
+    }
+    bins.iter()
+        .find_map(|(name, bin)| (name == uri).then_some(Cow::Borrowed(bin.as_slice())))
+        .ok_or_else(|| ExportError::BufferNotLoaded(uri.into()))
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Buffer {0} has no data")]
+    BufferMissing(usize),
+    #[error("Buffer {0} has an invalid data URI")]
+    InvalidDataUri(usize),
+    #[error("Buffer file {0} is not loaded")]
+    BufferNotLoaded(String),
+    #[error("Failed to serialize glTF: {0}")]
+    Json(#[from] gltf::json::Error),
+    #[error("Failed to write GLB: {0}")]
+    Glb(#[from] gltf::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        fixtures::{recording, rig},
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, convert},
+    };
+
+    fn converted() -> (Root, Vec<(String, Vec<u8>)>) {
+        let conversion = convert(
+            rig(),
+            "Test.bin",
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &ConvertOptions::default(),
+        )
+        .unwrap();
+        (conversion.root, vec![("Test.bin".into(), conversion.bin)])
+    }
+
+    #[test]
+    fn export_glb() {
+        let (root, bins) = converted();
+        let glb = to_glb(root.clone(), &bins).unwrap();
+
+        let gltf = gltf::Gltf::from_slice(&glb).unwrap();
+        let blob = gltf.blob.as_ref().unwrap();
+        assert_eq!(blob.as_slice(), bins[0].1.as_slice());
+        assert_eq!(gltf.buffers().len(), 1);
+        assert_eq!(gltf.animations().count(), 1);
+        for view in gltf.views() {
+            assert!(view.offset() + view.length() <= blob.len());
+        }
+    }
+
+    #[test]
+    fn export_embedded() {
+        let (mut root, bins) = converted();
+        embed(&mut root, &bins).unwrap();
+        let uri = root.buffers[0].uri.as_deref().unwrap();
+        assert!(uri.starts_with("data:"));
+
+        // embedded buffers survive packing into a GLB
+        let glb = to_glb(root, &[]).unwrap();
+        let gltf = gltf::Gltf::from_slice(&glb).unwrap();
+        assert_eq!(gltf.blob.unwrap(), bins[0].1);
+    }
+
+    #[test]
+    fn export_missing_buffer() {
+        let (root, _) = converted();
+        let result = to_glb(root, &[]);
+        assert!(matches!(result, Err(ExportError::BufferNotLoaded(name)) if name == "Test.bin"));
+    }
+
+    #[test]
+    fn export_rename_buffer() {
+        let (mut root, mut bins) = converted();
+        rename_buffer(&mut root, &mut bins, "Test.bin", "Walk.bin");
+        assert_eq!(root.buffers[0].uri.as_deref(), Some("Walk.bin"));
+        assert_eq!(bins[0].0, "Walk.bin");
+    }
+}
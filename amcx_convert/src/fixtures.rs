@@ -0,0 +1,84 @@
+//! Rig and recordings shared by the tests of the modules
+
+use std::time::Duration;
+
+use amcx_core::{Model, Record, Sample};
+use gltf::json::Root;
+use nalgebra::UnitQuaternion;
+
+use crate::{
+    calibration::Calibration,
+    mapping::Mapping,
+    to_gltf::{ConvertOptions, ConvertingError, ConvertingWarning, convert},
+};
+
+pub(crate) const RIG: &str = r#"{
+    "asset": { "version": "2.0" },
+    "nodes": [
+        { "name": "Upper", "children": [1, 2] },
+        { "name": "Lower", "rotation": [0.0, 0.0, 0.7071068, 0.7071068] },
+        { "name": "Prop" }
+    ],
+    "skins": [{ "joints": [0, 1] }]
+}"#;
+
+pub(crate) fn rig() -> Root {
+    Root::from_str(RIG).unwrap()
+}
+
+pub(crate) fn stream(count: usize, acc: [f32; 3], gyr: [f32; 3]) -> Vec<Record> {
+    (0..count)
+        .map(|i| Record {
+            timestamp: Duration::from_millis(10 * i as u64),
+            sample: Sample { acc, gyr },
+        })
+        .collect()
+}
+
+pub(crate) fn recording() -> Model {
+    vec![
+        (
+            "Upper".into(),
+            stream(100, [0.0, 0.0, 1.0], [0.1, 0.0, 0.0]),
+        ),
+        (
+            "Lower".into(),
+            stream(100, [0.0, 0.0, 1.0], [0.0, 0.2, 0.0]),
+        ),
+    ]
+}
+
+pub(crate) fn try_convert_with(
+    root: Root,
+    model: &Model,
+    calibration: &Calibration,
+    options: &ConvertOptions,
+) -> Result<Vec<ConvertingWarning>, ConvertingError> {
+    convert(
+        root,
+        "Test.bin",
+        model,
+        calibration,
+        &Mapping::default(),
+        options,
+    )
+    .map(|conversion| conversion.warnings)
+}
+
+pub(crate) fn noisy_track() -> (Vec<f32>, Vec<UnitQuaternion<f32>>) {
+    let times: Vec<_> = (0..200).map(|i| i as f32 * 0.01).collect();
+    let rotations = times
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let noise = if i % 2 == 0 { 0.02 } else { -0.02 };
+            let q = UnitQuaternion::from_euler_angles(noise, 0.0, t.min(0.5));
+            // sign flips must not disturb the filters
+            match i % 3 {
+                0 => UnitQuaternion::new_unchecked(-q.into_inner()),
+                _ => q,
+            }
+        })
+        .collect();
+    (times, rotations)
+}
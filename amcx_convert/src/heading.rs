@@ -0,0 +1,348 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+    time::Duration,
+};
+
+use amcx_core::{Record, Sample};
+use nalgebra::{UnitQuaternion, Vector3};
+
+use crate::root_motion;
+
+/// Keeps the headings of the sensors consistent, since without a magnetometer
+/// every AHRS drifts around the vertical axis on its own
+///
+/// With a calibration recording the headings are referenced to its start,
+/// where the subject holds the calibration pose: the AHRS of every sensor runs on
+/// from the calibration recording into the recording, which assumes that the sensors
+/// are neither moved nor restarted in between.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadingCorrection {
+    /// Removes the gyroscope bias measured while each sensor is still
+    pub gyro_bias: bool,
+    /// Restores the heading of every sensor relative to its parent sensor whenever both are still,
+    /// which assumes the subject returns to the same stance
+    pub chain: bool,
+    /// Largest deviation of the acceleration norm from 1 g in a still sample
+    pub acc_threshold: f32,
+    /// Largest angular speed in rad/s in a still sample
+    pub gyr_threshold: f32,
+}
+impl Default for HeadingCorrection {
+    fn default() -> Self {
+        HeadingCorrection {
+            gyro_bias: true,
+            chain: true,
+            acc_threshold: 0.05,
+            gyr_threshold: 0.3,
+        }
+    }
+}
+
+impl HeadingCorrection {
+    pub fn stillness(&self, stream: &[Record]) -> Vec<bool> {
+        root_motion::stillness(stream, self.acc_threshold, self.gyr_threshold)
+    }
+
+    /// Subtracts the mean angular velocity of the still periods,
+    /// interpolated in between them
+    pub fn remove_bias(&self, stream: &[Record], still: &[bool]) -> Vec<Record> {
+        let anchors: Vec<_> = still_periods(still)
+            .into_iter()
+            .map(|period| {
+                let sum: Vector3<f32> = stream[period.clone()]
+                    .iter()
+                    .map(|record| Vector3::from(record.sample.gyr))
+                    .sum();
+                (centre(&period), sum / period.len() as f32)
+            })
+            .collect();
+        let Some(bias) = spread(&anchors, stream.len(), |a, b, t| a.lerp(&b, t)) else {
+            return stream.to_vec();
+        };
+
+        stream
+            .iter()
+            .zip(bias)
+            .map(|(record, bias)| Record {
+                timestamp: record.timestamp,
+                sample: Sample {
+                    acc: record.sample.acc,
+                    gyr: (Vector3::from(record.sample.gyr) - bias).into(),
+                },
+            })
+            .collect()
+    }
+}
+
+/// The calibration recording followed by the recording as one stream,
+/// with the timestamps of the recording moved behind the calibration
+pub fn continued(calibration: &[Record], stream: &[Record]) -> Vec<Record> {
+    let (Some(last), Some(first)) = (calibration.last(), stream.first()) else {
+        return [calibration, stream].concat();
+    };
+    // one step of the recording between both
+    let step = stream.get(1).map_or(Duration::ZERO, |second| {
+        second.timestamp.saturating_sub(first.timestamp)
+    });
+    let offset = last.timestamp + step;
+    calibration
+        .iter()
+        .cloned()
+        .chain(stream.iter().map(|record| Record {
+            timestamp: offset + record.timestamp.saturating_sub(first.timestamp),
+            sample: record.sample.clone(),
+        }))
+        .collect()
+}
+
+/// Rotates the AHRS world frame around the vertical axis, so that the first heading is zero
+pub fn align(rotations: &mut [UnitQuaternion<f32>]) {
+    let Some(first) = rotations.first() else {
+        return;
+    };
+    let correction = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -heading(first));
+    rotations.iter_mut().for_each(|q| *q = correction * *q);
+}
+
+/// Rotates the child sensor around the vertical axis, so that at every period in which
+/// both sensors are still its heading relative to the parent matches the first such period
+pub fn align_to_parent(
+    parent: &[UnitQuaternion<f32>],
+    child: &mut [UnitQuaternion<f32>],
+    still: &[bool],
+) {
+    let relative: Vec<_> = parent
+        .iter()
+        .zip(child.iter())
+        .map(|(p, c)| wrap(heading(c) - heading(p)))
+        .collect();
+    let periods = still_periods(&still[..still.len().min(relative.len())]);
+    let Some(reference) = periods.first().map(|period| relative[period.start]) else {
+        return;
+    };
+
+    let mut previous = 0.0;
+    let anchors: Vec<_> = periods
+        .into_iter()
+        .map(|period| {
+            let sum: f32 = relative[period.clone()]
+                .iter()
+                .map(|r| wrap(r - reference))
+                .sum();
+            // unwrapped, so that drift past half a turn is interpolated the short way
+            let drift = previous + wrap(sum / period.len() as f32 - previous);
+            previous = drift;
+            (centre(&period), drift)
+        })
+        .collect();
+    let Some(drift) = spread(&anchors, child.len(), |a, b, t| a + (b - a) * t) else {
+        return;
+    };
+
+    for (q, drift) in child.iter_mut().zip(drift) {
+        *q = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -drift) * *q;
+    }
+}
+
+/// Angle of the rotation around the vertical axis, from its swing-twist decomposition
+pub fn heading(q: &UnitQuaternion<f32>) -> f32 {
+    wrap(2.0 * f32::atan2(q.k, q.w))
+}
+
+fn wrap(angle: f32) -> f32 {
+    let angle = angle.rem_euclid(TAU);
+    match angle > PI {
+        true => angle - TAU,
+        false => angle,
+    }
+}
+
+fn still_periods(still: &[bool]) -> Vec<Range<usize>> {
+    let mut periods = Vec::new();
+    let mut start = None;
+    for (i, still) in still.iter().enumerate() {
+        match (still, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                periods.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        periods.push(s..still.len());
+    }
+    periods
+}
+
+fn centre(period: &Range<usize>) -> usize {
+    (period.start + period.end - 1) / 2
+}
+
+/// Values for `len` samples, interpolated between the anchors and held outside of them
+fn spread<T: Copy>(
+    anchors: &[(usize, T)],
+    len: usize,
+    lerp: impl Fn(T, T, f32) -> T,
+) -> Option<Vec<T>> {
+    let (first, last) = (anchors.first()?, anchors.last()?);
+    let mut next = 0;
+    let values = (0..len)
+        .map(|i| {
+            while next < anchors.len() && anchors[next].0 < i {
+                next += 1;
+            }
+            match next {
+                0 => first.1,
+                n if n == anchors.len() => last.1,
+                n => {
+                    let ((i0, v0), (i1, v1)) = (anchors[n - 1], anchors[n]);
+                    lerp(v0, v1, (i - i0) as f32 / (i1 - i0) as f32)
+                }
+            }
+        })
+        .collect();
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use gltf::json::Index;
+
+    use super::*;
+    use crate::{
+        calibration::{Calibration, CalibrationMethod},
+        fixtures::{rig, stream},
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, calculate_rotations},
+    };
+
+    #[test]
+    fn heading_gyro_bias() {
+        let bias = [0.01, -0.02, 0.05];
+        let mut stream = stream(100, [0.0, 0.0, 1.0], bias);
+        for record in &mut stream[30..60] {
+            record.sample.acc = [0.5, 0.0, 1.0];
+            record.sample.gyr[2] += 1.0;
+        }
+        let correction = HeadingCorrection::default();
+        let still = correction.stillness(&stream);
+        let corrected = correction.remove_bias(&stream, &still);
+        assert!(corrected.iter().all(|record| {
+            let gyr = Vector3::from(record.sample.gyr);
+            (gyr - Vector3::new(0.0, 0.0, gyr.z)).norm() < 1e-6
+        }));
+        assert!((corrected[45].sample.gyr[2] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn heading_chain_drift() {
+        let parent = vec![UnitQuaternion::identity(); 100];
+        let tilt = UnitQuaternion::from_euler_angles(0.3, 0.0, 0.0);
+        // heading drifts by half a radian over the recording
+        let mut child: Vec<_> = (0..100)
+            .map(|i| UnitQuaternion::from_euler_angles(0.0, 0.0, 0.005 * i as f32) * tilt)
+            .collect();
+        let still: Vec<_> = (0..100)
+            .map(|i| i < 10 || (45..55).contains(&i) || i >= 90)
+            .collect();
+        align_to_parent(&parent, &mut child, &still);
+        let worst = child.iter().map(|q| heading(q).abs()).fold(0.0, f32::max);
+        assert!(worst < 0.03, "{worst}");
+        // only the heading changes, not the tilt
+        assert!(
+            child
+                .iter()
+                .all(|q| ((q * Vector3::z()).z - 0.3f32.cos()).abs() < 1e-5)
+        );
+    }
+
+    #[test]
+    fn convert_with_heading_correction() {
+        // both sensors are still while their gyroscopes drift around the vertical
+        let model = vec![
+            (
+                "Upper".into(),
+                stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, 0.05]),
+            ),
+            (
+                "Lower".into(),
+                stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, -0.03]),
+            ),
+        ];
+        let rest = UnitQuaternion::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_2);
+        let drift = |heading| {
+            let options = ConvertOptions {
+                heading,
+                ..Default::default()
+            };
+            let rotations = calculate_rotations(
+                &model,
+                &rig(),
+                &Calibration::default(),
+                &Mapping::default(),
+                &options,
+                &mut Vec::new(),
+            )
+            .unwrap();
+            let upper = rotations[&Index::new(0)].last().unwrap().angle();
+            let lower = rotations[&Index::new(1)].last().unwrap().angle_to(&rest);
+            upper.max(lower)
+        };
+
+        assert!(drift(None) > 0.04);
+        let corrected = drift(Some(HeadingCorrection::default()));
+        assert!(corrected < 1e-3, "{corrected}");
+    }
+
+    #[test]
+    fn heading_from_calibration() {
+        // Lower turns by a quarter around the vertical after the pose,
+        // and stays there while the recording starts
+        let mut lower = stream(200, [0.0, 0.0, 1.0], [0.0; 3]);
+        for record in &mut lower[100..] {
+            record.sample.gyr[2] = std::f32::consts::FRAC_PI_2;
+        }
+        let reference = vec![
+            ("Upper".into(), stream(200, [0.0, 0.0, 1.0], [0.0; 3])),
+            ("Lower".into(), lower),
+        ];
+        let model = vec![
+            ("Upper".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+            ("Lower".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+        ];
+        let calibration = Calibration {
+            reference: Some(&reference),
+            method: CalibrationMethod::Pose,
+            ..Default::default()
+        };
+        let lower_turn = |heading| {
+            let options = ConvertOptions {
+                heading,
+                ..Default::default()
+            };
+            let rotations = calculate_rotations(
+                &model,
+                &rig(),
+                &calibration,
+                &Mapping::default(),
+                &options,
+                &mut Vec::new(),
+            )
+            .unwrap();
+            let rest = UnitQuaternion::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_2);
+            rotations[&Index::new(1)][0].angle_to(&rest)
+        };
+
+        // without the correction the recording is taken to start in the calibration pose
+        assert!(lower_turn(None) < 1e-3);
+        let heading = HeadingCorrection {
+            chain: false,
+            ..Default::default()
+        };
+        let turn = lower_turn(Some(heading));
+        assert!((turn - std::f32::consts::FRAC_PI_2).abs() < 0.05, "{turn}");
+    }
+}
@@ -1 +1,22 @@
-pub mod to_gltf;
\ No newline at end of file
+pub mod angles;
+pub mod calibration;
+pub mod chains;
+pub mod euler;
+pub mod export;
+pub mod heading;
+pub mod limits;
+pub mod mapping;
+pub mod provenance;
+pub mod retarget;
+pub mod rom;
+pub mod root_motion;
+pub mod scaling;
+pub mod smoothing;
+pub mod to_bvh;
+pub mod to_gltf;
+pub mod to_usd;
+pub mod tracks;
+pub mod world_frame;
+
+#[cfg(test)]
+mod fixtures;
@@ -0,0 +1,350 @@
+use amcx_core::mounting::{Axis, SignedAxis};
+use nalgebra::{Quaternion, Unit, UnitQuaternion, Vector3};
+use thiserror::Error;
+
+use crate::to_gltf::Joint;
+
+/// Anatomical limits of the joints, relative to their rest pose.
+///
+/// The text format has one joint per line, with angles in degrees:
+/// ```text
+/// # JOINT  hinge        AXIS  MIN  MAX
+/// # JOINT  swing-twist  AXIS  TWIST_MIN  TWIST_MAX  SWING
+/// Forearm.L   hinge        +x    0  150
+/// UpperArm.L  swing-twist  +y  -90   90  120
+/// ```
+/// where `AXIS` is in the local frame of the joint.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JointLimits {
+    limits: Vec<(Joint, JointLimit)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JointLimit {
+    /// Rotation around a single axis, rotation around the other axes is removed
+    Hinge {
+        axis: Unit<Vector3<f32>>,
+        min: f32,
+        max: f32,
+    },
+    /// Twist around the axis and a cone the axis may swing in
+    SwingTwist {
+        axis: Unit<Vector3<f32>>,
+        twist_min: f32,
+        twist_max: f32,
+        swing: f32,
+    },
+}
+
+impl JointLimits {
+    pub fn parse(source: &str) -> Result<JointLimits, LimitsError> {
+        let mut limits = JointLimits::default();
+        let lines = source
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+            .enumerate()
+            .filter_map(|(i, s)| (!s.is_empty()).then_some((i + 1, s)));
+
+        for (line, source) in lines {
+            let (joint, limit) = parse_limit(source).map_err(|err| err.at(line))?;
+            if limits.get(&joint).is_some() {
+                return Err(InnerLimitsError::JointDuplicate(joint).at(line));
+            }
+            limits.limits.push((joint, limit));
+        }
+        Ok(limits)
+    }
+
+    pub fn get(&self, joint: &str) -> Option<&JointLimit> {
+        self.limits
+            .iter()
+            .find_map(|(j, limit)| (j == joint).then_some(limit))
+    }
+
+    pub fn set(&mut self, joint: Joint, limit: JointLimit) {
+        match self.limits.iter_mut().find(|(j, _)| *j == joint) {
+            Some((_, old)) => *old = limit,
+            None => self.limits.push((joint, limit)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Joint, JointLimit)> {
+        self.limits.iter()
+    }
+}
+
+impl JointLimit {
+    /// Rotations that differ by less than this many radians count as within the limits
+    const EPSILON: f32 = 1e-5;
+
+    /// Clamps a rotation relative to the rest pose into the limits,
+    /// returns whether it was out of range
+    ///
+    /// A hinge also drops any rotation off its axis, which counts as out of range as well.
+    pub fn project(&self, q: &UnitQuaternion<f32>) -> (UnitQuaternion<f32>, bool) {
+        let projected = self.clamp(q);
+        // half the angle, without the rounding of `acos` close to identity
+        let change = (projected.inverse() * q).imag().norm();
+        (projected, change > (Self::EPSILON / 2.0).sin())
+    }
+
+    fn clamp(&self, q: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+        match *self {
+            JointLimit::Hinge { axis, min, max } => {
+                let (_, twist) = twist_angle(q, &axis);
+                let clamped = twist.clamp(min.to_radians(), max.to_radians());
+                UnitQuaternion::from_axis_angle(&axis, clamped)
+            }
+            JointLimit::SwingTwist {
+                axis,
+                twist_min,
+                twist_max,
+                swing,
+            } => {
+                let (swing_rotation, twist) = twist_angle(q, &axis);
+                let clamped_twist = twist.clamp(twist_min.to_radians(), twist_max.to_radians());
+                let swing = swing.to_radians();
+                // rebuilt only when clamped, the angle is rounded close to identity
+                let swing_rotation = match swing_rotation.axis() {
+                    Some(swing_axis) if swing_rotation.angle() > swing => {
+                        UnitQuaternion::from_axis_angle(&swing_axis, swing)
+                    }
+                    _ => swing_rotation,
+                };
+                swing_rotation * UnitQuaternion::from_axis_angle(&axis, clamped_twist)
+            }
+        }
+    }
+}
+
+/// Splits `q` into `swing * twist` and returns the swing with the twist angle around `axis`
+fn twist_angle(q: &UnitQuaternion<f32>, axis: &Unit<Vector3<f32>>) -> (UnitQuaternion<f32>, f32) {
+    let projection = axis.into_inner() * q.imag().dot(axis);
+    let twist = Quaternion::from_parts(q.w, projection);
+    if twist.norm() <= f32::EPSILON {
+        // swung by half a turn, the twist is undefined
+        return (*q, 0.0);
+    }
+    let twist = UnitQuaternion::new_normalize(twist);
+    let swing = q * twist.inverse();
+
+    let angle = 2.0 * f32::atan2(twist.imag().dot(axis), twist.w);
+    // the double cover makes the angle range over a full turn in both directions
+    let angle = match angle {
+        a if a > std::f32::consts::PI => a - std::f32::consts::TAU,
+        a if a < -std::f32::consts::PI => a + std::f32::consts::TAU,
+        a => a,
+    };
+    (swing, angle)
+}
+
+fn parse_limit(source: &str) -> Result<(Joint, JointLimit), InnerLimitsError> {
+    let mut source = source.split_whitespace();
+    let mut next = |what: &str| {
+        source
+            .next()
+            .ok_or_else(|| InnerLimitsError::TokenExpected(what.into()))
+    };
+    let joint = next("joint")?.to_string();
+    let kind = next("limit kind")?;
+    let axis = next("axis")?;
+    let axis: SignedAxis = axis
+        .parse()
+        .map_err(|_| InnerLimitsError::InvalidAxis(axis.into()))?;
+    let axis = axis_vector(axis);
+    let mut angle = |what: &str| {
+        let angle = next(what)?;
+        angle
+            .parse::<f32>()
+            .ok()
+            .filter(|angle| angle.is_finite())
+            .ok_or_else(|| InnerLimitsError::InvalidAngle(angle.into()))
+    };
+    let range = |min: f32, max: f32| match min <= max {
+        true => Ok(()),
+        false => Err(InnerLimitsError::InvalidRange(min, max)),
+    };
+
+    let limit = match kind {
+        "hinge" => {
+            let (min, max) = (angle("minimum angle")?, angle("maximum angle")?);
+            range(min, max)?;
+            JointLimit::Hinge { axis, min, max }
+        }
+        "swing-twist" => {
+            let (twist_min, twist_max) = (angle("minimum twist")?, angle("maximum twist")?);
+            range(twist_min, twist_max)?;
+            let swing = angle("swing")?;
+            range(0.0, swing)?;
+            JointLimit::SwingTwist {
+                axis,
+                twist_min,
+                twist_max,
+                swing,
+            }
+        }
+        kind => return Err(InnerLimitsError::UnknownKind(kind.into())),
+    };
+    if let Some(what) = source.next() {
+        return Err(InnerLimitsError::TokenUnexpected {
+            expected: "nothing".into(),
+            found: what.into(),
+        });
+    }
+    Ok((joint, limit))
+}
+
+fn axis_vector(axis: SignedAxis) -> Unit<Vector3<f32>> {
+    let v = match axis.axis {
+        Axis::X => Vector3::x_axis(),
+        Axis::Y => Vector3::y_axis(),
+        Axis::Z => Vector3::z_axis(),
+    };
+    match axis.negative {
+        true => -v,
+        false => v,
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Line {line}: {inner}")]
+pub struct LimitsError {
+    line: usize,
+    inner: InnerLimitsError,
+}
+
+#[derive(Error, Debug)]
+pub enum InnerLimitsError {
+    #[error("expected {0}, but found nothing")]
+    TokenExpected(String),
+    #[error("expected {expected}, but found {found}")]
+    TokenUnexpected { expected: String, found: String },
+    #[error("duplicate joints are not allowed: {0}")]
+    JointDuplicate(String),
+    #[error("unknown limit {0}, expected \"hinge\" or \"swing-twist\"")]
+    UnknownKind(String),
+    #[error("invalid axis {0}, expected one like \"+x\" or \"-z\"")]
+    InvalidAxis(String),
+    #[error("invalid angle {0}, expected degrees")]
+    InvalidAngle(String),
+    #[error("invalid range from {0}° to {1}°")]
+    InvalidRange(f32, f32),
+}
+impl InnerLimitsError {
+    pub fn at(self, line: usize) -> LimitsError {
+        LimitsError { line, inner: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        fixtures::{recording, rig, try_convert_with},
+        to_gltf::{ConvertOptions, ConvertingWarning},
+    };
+
+    #[test]
+    fn limits_parse() {
+        let limits = JointLimits::parse(
+            "# elbow and shoulder\nForearm hinge +x 0 150\nUpperArm swing-twist -y -90 90 120 # cone\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            limits.get("Forearm"),
+            Some(JointLimit::Hinge {
+                min: 0.0,
+                max: 150.0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            limits.get("UpperArm"),
+            Some(JointLimit::SwingTwist { axis, swing: 120.0, .. }) if axis.y == -1.0
+        ));
+
+        for invalid in [
+            "Forearm hinge +x 0",
+            "Forearm hinge +w 0 150",
+            "Forearm hinge +x 150 0",
+            "Forearm ball +x 0 150",
+            "Forearm hinge +x 0 150 7",
+            "Forearm hinge +x 0 150\nForearm hinge +y 0 150",
+        ] {
+            assert!(JointLimits::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn limits_projection() {
+        let hinge = JointLimit::Hinge {
+            axis: Vector3::x_axis(),
+            min: 0.0,
+            max: 150.0,
+        };
+        // bent backwards and slightly off the axis
+        let q = UnitQuaternion::from_euler_angles(-0.3, 0.05, 0.0);
+        let (projected, hit) = hinge.project(&q);
+        assert!(hit);
+        assert!(projected.angle() < 1e-5);
+        let q = UnitQuaternion::from_euler_angles(1.0, 0.0, 0.0);
+        assert_eq!(hinge.project(&q), (q, false));
+        // within the range, but off the axis
+        let q = UnitQuaternion::from_euler_angles(1.0, 0.05, 0.0);
+        let (projected, hit) = hinge.project(&q);
+        assert!(hit);
+        assert!(projected.angle_to(&UnitQuaternion::from_euler_angles(1.0, 0.0, 0.0)) < 1e-3);
+
+        let cone = JointLimit::SwingTwist {
+            axis: Vector3::y_axis(),
+            twist_min: -10.0,
+            twist_max: 10.0,
+            swing: 45.0,
+        };
+        let q = UnitQuaternion::from_euler_angles(1.2, 0.5, 0.0);
+        let (projected, hit) = cone.project(&q);
+        assert!(hit);
+        let swing = (projected * Vector3::y()).angle(&Vector3::y());
+        assert!((swing - 45f32.to_radians()).abs() < 1e-4, "{swing}");
+        // splitting into swing and twist does not count as a change
+        let q = UnitQuaternion::from_euler_angles(0.2, 0.1, 0.0);
+        assert!(!cone.project(&q).1);
+    }
+
+    #[test]
+    fn convert_with_limits() {
+        let mut limits = JointLimits::default();
+        limits.set(
+            "Lower".into(),
+            JointLimit::SwingTwist {
+                axis: Vector3::x_axis(),
+                twist_min: -1.0,
+                twist_max: 1.0,
+                swing: 1.0,
+            },
+        );
+        let options = ConvertOptions {
+            limits,
+            ..Default::default()
+        };
+        let warnings =
+            try_convert_with(rig(), &recording(), &Calibration::default(), &options).unwrap();
+        let hits = warnings.iter().find_map(|warning| match warning {
+            ConvertingWarning::JointLimited {
+                joint,
+                hits,
+                samples,
+            } if joint == "Lower" => Some((*hits, *samples)),
+            _ => None,
+        });
+        assert!(
+            matches!(hits, Some((hits, 100)) if hits > 0 && hits < 100),
+            "{hits:?}"
+        );
+    }
+}
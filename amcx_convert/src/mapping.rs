@@ -0,0 +1,351 @@
+use std::fmt::Display;
+
+use amcx_core::{Model, Record, Sample, Sensor, mounting::Mounting};
+use thiserror::Error;
+
+use crate::to_gltf::Joint;
+
+/// Couples sensors with joints of the target model.
+///
+/// Sensors that are not listed are coupled with the joint of the same name.
+///
+/// The text format has one sensor per line:
+/// ```text
+/// # SENSOR JOINT [MOUNTING]
+/// ArmUpR  UpperArm.R  +x-z+y
+/// ArmLoR  LowerArm.R  rpy:0,0,90
+/// Spare   -
+/// ```
+/// where `-` ignores the sensor and `MOUNTING` is how the sensor is strapped on.
+///
+/// The mapping is the only place where mountings are applied: the mountings stored
+/// in the header of a recording are fed into it with [`Mapping::mount`].
+/// A mounting turns the raw acc and gyr samples into the sensor frame the converter sees,
+/// orientation estimation and calibration then work on the remapped samples.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mapping {
+    couplings: Vec<(Sensor, Coupling)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Coupling {
+    Joint {
+        joint: Joint,
+        mounting: Option<Mounting>,
+    },
+    Ignored,
+}
+
+impl Mapping {
+    pub const IGNORED: &str = "-";
+
+    pub fn parse(source: &str) -> Result<Mapping, MappingError> {
+        let mut mapping = Mapping::default();
+        let lines = source
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+            .enumerate()
+            .filter_map(|(i, s)| (!s.is_empty()).then_some((i + 1, s)));
+
+        for (line, source) in lines {
+            let (sensor, coupling) = parse_coupling(source).map_err(|err| err.at(line))?;
+            if mapping.get(&sensor).is_some() {
+                return Err(InnerMappingError::SensorDuplicate(sensor).at(line));
+            }
+            mapping.couplings.push((sensor, coupling));
+        }
+        Ok(mapping)
+    }
+
+    pub fn get(&self, sensor: &str) -> Option<&Coupling> {
+        self.couplings
+            .iter()
+            .find_map(|(s, coupling)| (s == sensor).then_some(coupling))
+    }
+
+    pub fn set(&mut self, sensor: Sensor, coupling: Coupling) {
+        match self.couplings.iter_mut().find(|(s, _)| *s == sensor) {
+            Some((_, old)) => *old = coupling,
+            None => self.couplings.push((sensor, coupling)),
+        }
+    }
+
+    pub fn remove(&mut self, sensor: &str) {
+        self.couplings.retain(|(s, _)| s != sensor);
+    }
+
+    /// Sets the mounting of the sensor, keeping or adding its coupling.
+    ///
+    /// A sensor that is not listed yet is coupled with the joint of the same name,
+    /// an ignored sensor stays ignored.
+    pub fn mount(&mut self, sensor: &str, mounting: Option<Mounting>) {
+        match self.couplings.iter_mut().find(|(s, _)| s == sensor) {
+            Some((_, Coupling::Joint { mounting: old, .. })) => *old = mounting,
+            Some((_, Coupling::Ignored)) => (),
+            None if mounting.is_some() => self.couplings.push((
+                sensor.into(),
+                Coupling::Joint {
+                    joint: sensor.into(),
+                    mounting,
+                },
+            )),
+            None => (),
+        }
+    }
+
+    pub fn mounting(&self, sensor: &str) -> Option<Mounting> {
+        match self.get(sensor) {
+            Some(Coupling::Joint { mounting, .. }) => *mounting,
+            _ => None,
+        }
+    }
+
+    /// Mountings of all sensors that have one
+    pub fn mountings(&self) -> Vec<(Sensor, Mounting)> {
+        self.couplings
+            .iter()
+            .filter_map(|(sensor, coupling)| match coupling {
+                Coupling::Joint {
+                    mounting: Some(mounting),
+                    ..
+                } => Some((sensor.clone(), *mounting)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Joint driven by the sensor, `None` if the sensor is ignored
+    pub fn joint<'a>(&'a self, sensor: &'a str) -> Option<&'a str> {
+        match self.get(sensor) {
+            Some(Coupling::Joint { joint, .. }) => Some(joint),
+            Some(Coupling::Ignored) => None,
+            None => Some(sensor),
+        }
+    }
+
+    /// Drops ignored sensors and applies the mountings of the rest
+    pub fn apply(&self, model: &Model) -> Model {
+        model
+            .iter()
+            .filter_map(|(sensor, stream)| match self.get(sensor) {
+                Some(Coupling::Ignored) => None,
+                Some(Coupling::Joint {
+                    mounting: Some(mounting),
+                    ..
+                }) => {
+                    let stream = stream
+                        .iter()
+                        .map(|record| Record {
+                            timestamp: record.timestamp,
+                            sample: Sample {
+                                acc: mounting.apply(record.sample.acc),
+                                gyr: mounting.apply(record.sample.gyr),
+                            },
+                        })
+                        .collect();
+                    Some((sensor.clone(), stream))
+                }
+                _ => Some((sensor.clone(), stream.clone())),
+            })
+            .collect()
+    }
+}
+impl Display for Mapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (sensor, coupling) in &self.couplings {
+            match coupling {
+                Coupling::Joint {
+                    joint,
+                    mounting: Some(mounting),
+                } => writeln!(f, "{sensor} {joint} {mounting}")?,
+                Coupling::Joint { joint, .. } => writeln!(f, "{sensor} {joint}")?,
+                Coupling::Ignored => writeln!(f, "{sensor} {}", Mapping::IGNORED)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_coupling(source: &str) -> Result<(Sensor, Coupling), InnerMappingError> {
+    let mut source = source.split_whitespace();
+    let sensor = source
+        .next()
+        .ok_or(InnerMappingError::TokenExpected("sensor".into()))?;
+    let joint = source
+        .next()
+        .ok_or(InnerMappingError::TokenExpected("joint".into()))?;
+
+    let mounting: Vec<_> = source.collect();
+    let coupling = match joint {
+        Mapping::IGNORED => {
+            if let Some(what) = mounting.first() {
+                return Err(InnerMappingError::TokenUnexpected {
+                    expected: "nothing".into(),
+                    found: what.to_string(),
+                });
+            }
+            Coupling::Ignored
+        }
+        joint => {
+            let mounting = match mounting.as_slice() {
+                [] => None,
+                mounting => Some(
+                    mounting
+                        .join(" ")
+                        .parse()
+                        .map_err(|_| InnerMappingError::InvalidMounting(mounting.join(" ")))?,
+                ),
+            };
+            Coupling::Joint {
+                joint: joint.into(),
+                mounting,
+            }
+        }
+    };
+    Ok((sensor.into(), coupling))
+}
+
+#[derive(Error, Debug)]
+#[error("Line {line}: {inner}")]
+pub struct MappingError {
+    line: usize,
+    inner: InnerMappingError,
+}
+
+#[derive(Error, Debug)]
+pub enum InnerMappingError {
+    #[error("expected {0}, but found nothing")]
+    TokenExpected(String),
+    #[error("expected {expected}, but found {found}")]
+    TokenUnexpected { expected: String, found: String },
+    #[error("duplicate sensors are not allowed: {0}")]
+    SensorDuplicate(String),
+    #[error("invalid mounting {0}, expected axes like +x-z+y or a rotation like rpy:90,0,180")]
+    InvalidMounting(String),
+}
+impl InnerMappingError {
+    pub fn at(self, line: usize) -> MappingError {
+        MappingError { line, inner: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amcx_core::mounting::AxisRemap;
+
+    use super::*;
+    use crate::{
+        calibration::{Calibration, CalibrationMethod},
+        fixtures::{recording, rig, stream},
+        to_gltf::{ConvertOptions, ConvertingError, calculate_rotations},
+    };
+
+    #[test]
+    fn mapping_parse() {
+        let source = "# SENSOR JOINT [MOUNTING]
+A Upper +x -z +y
+B Lower rpy:0,0,90 # arm
+C -";
+        let mapping = Mapping::parse(source).unwrap();
+        assert_eq!(mapping.joint("A"), Some("Upper"));
+        assert_eq!(mapping.joint("C"), None);
+        assert_eq!(mapping.joint("D"), Some("D"));
+        assert_eq!(
+            mapping.mounting("A"),
+            Some(Mounting::Axes("+x -z +y".parse().unwrap()))
+        );
+        assert_eq!(
+            mapping.mounting("B"),
+            Some(Mounting::Rotation([0.0, 0.0, 90.0]))
+        );
+        assert_eq!(Mapping::parse(&mapping.to_string()).unwrap(), mapping);
+
+        for invalid in ["A", "A Upper +x +y -z", "A - +x -z +y", "A Upper\nA Lower"] {
+            assert!(Mapping::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn header_mountings() {
+        let mut mapping = Mapping::parse("A Upper +y -x +z\nC -").unwrap();
+        // the header replaces the mounting of the mapping file
+        mapping.mount("A", Some(Mounting::Rotation([0.0, 0.0, 90.0])));
+        mapping.mount("B", Some(Mounting::Axes(AxisRemap::IDENTITY)));
+        mapping.mount("C", Some(Mounting::Axes(AxisRemap::IDENTITY)));
+        mapping.mount("D", None);
+        assert_eq!(
+            mapping.mounting("A"),
+            Some(Mounting::Rotation([0.0, 0.0, 90.0]))
+        );
+        assert_eq!(mapping.joint("A"), Some("Upper"));
+        assert_eq!(mapping.joint("B"), Some("B"));
+        assert_eq!(mapping.joint("C"), None);
+        assert!(mapping.get("D").is_none());
+        assert_eq!(
+            mapping.mountings(),
+            vec![
+                ("A".into(), Mounting::Rotation([0.0, 0.0, 90.0])),
+                ("B".into(), Mounting::Axes(AxisRemap::IDENTITY)),
+            ]
+        );
+        mapping.mount("A", None);
+        assert_eq!(mapping.mounting("A"), None);
+    }
+
+    #[test]
+    fn mounting_before_calibration() {
+        let mapping = Mapping::parse("Lower Lower -y +x +z").unwrap();
+        let model = recording();
+        let remapped = mapping.apply(&model);
+        assert_eq!(remapped[1].1[0].sample.gyr, [-0.2, 0.0, 0.0]);
+        assert_eq!(remapped[0].1[0].sample.gyr, [0.1, 0.0, 0.0]);
+
+        // the reference is remapped as well, before the calibration is computed
+        let reference = vec![
+            ("Upper".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+            ("Lower".into(), stream(100, [0.0, 1.0, 0.0], [0.0; 3])),
+        ];
+        let remapped_reference = mapping.apply(&reference);
+        let rotations = |model, reference, mapping| {
+            let calibration = Calibration {
+                reference: Some(reference),
+                method: CalibrationMethod::Pose,
+                ..Default::default()
+            };
+            calculate_rotations(
+                model,
+                &rig(),
+                &calibration,
+                mapping,
+                &ConvertOptions::default(),
+                &mut Vec::new(),
+            )
+            .unwrap()
+        };
+        let mapped = rotations(&model, &reference, &mapping);
+        let expected = rotations(&remapped, &remapped_reference, &Mapping::default());
+        for (joint, rotations) in expected {
+            for (q, expected) in mapped[&joint].iter().zip(rotations) {
+                assert!(q.angle_to(&expected) < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn sensors_sharing_joint() {
+        let mapping = Mapping::parse("Upper Upper\nLower Upper").unwrap();
+        let result = calculate_rotations(
+            &recording(),
+            &rig(),
+            &Calibration::default(),
+            &mapping,
+            &ConvertOptions::default(),
+            &mut Vec::new(),
+        );
+        assert!(matches!(
+            result,
+            Err(ConvertingError::SensorsShareJoint(first, second))
+                if first == "Upper" && second == "Lower"
+        ));
+    }
+}
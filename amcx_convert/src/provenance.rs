@@ -0,0 +1,230 @@
+use gltf::json::{Extras, Value, extras::RawValue};
+
+use nalgebra::{Unit, Vector3};
+
+use crate::{
+    calibration::Calibration, limits::JointLimit, mapping::Mapping, to_gltf::ConvertOptions,
+};
+
+/// Where an exported animation comes from, written into the glTF extras
+/// so that the conversion can be traced back and repeated
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Provenance {
+    /// Application and version that converted the recordings, like `Axon 0.1.0`
+    pub generator: String,
+    /// File names of the recordings, one per animation
+    pub recordings: Vec<String>,
+    /// File name of the calibration recording
+    pub calibration: Option<String>,
+}
+
+impl Provenance {
+    /// Extras of the asset, with every recording
+    pub fn asset_extras(
+        &self,
+        calibration: &Calibration,
+        mapping: &Mapping,
+        options: &ConvertOptions,
+    ) -> Extras {
+        let recordings = self.recordings.iter().cloned().map(Value::from);
+        self.extras(
+            ("recordings", Value::Array(recordings.collect())),
+            calibration,
+            mapping,
+            options,
+        )
+    }
+
+    /// Extras of the animation converted from the recording at `take`
+    pub fn animation_extras(
+        &self,
+        take: usize,
+        calibration: &Calibration,
+        mapping: &Mapping,
+        options: &ConvertOptions,
+    ) -> Extras {
+        let recording = self.recordings.get(take).cloned();
+        self.extras(
+            ("recording", Value::from(recording)),
+            calibration,
+            mapping,
+            options,
+        )
+    }
+
+    fn extras(
+        &self,
+        source: (&str, Value),
+        calibration: &Calibration,
+        mapping: &Mapping,
+        options: &ConvertOptions,
+    ) -> Extras {
+        let provenance = Value::from_iter([
+            ("generator", Value::from(self.generator.clone())),
+            source,
+            ("calibration", Value::from(self.calibration.clone())),
+            ("settings", settings(calibration, mapping, options)),
+        ]);
+        RawValue::from_string(Value::from_iter([("provenance", provenance)]).to_string()).ok()
+    }
+}
+
+/// Every setting that affects the converted motion
+fn settings(calibration: &Calibration, mapping: &Mapping, options: &ConvertOptions) -> Value {
+    let smoothing = options
+        .smoothing
+        .joints
+        .iter()
+        .map(|(joint, filter)| (joint.clone(), Value::from(filter.to_string())));
+    let limits = options.limits.iter().map(|(joint, limit)| {
+        let axis = |axis: &Unit<Vector3<f32>>| Value::from_iter([axis.x, axis.y, axis.z]);
+        let limit = match limit {
+            JointLimit::Hinge { axis: a, min, max } => Value::from_iter([
+                ("kind", Value::from("hinge")),
+                ("axis", axis(a)),
+                ("min", Value::from(*min)),
+                ("max", Value::from(*max)),
+            ]),
+            JointLimit::SwingTwist {
+                axis: a,
+                twist_min,
+                twist_max,
+                swing,
+            } => Value::from_iter([
+                ("kind", Value::from("swing-twist")),
+                ("axis", axis(a)),
+                ("twist_min", Value::from(*twist_min)),
+                ("twist_max", Value::from(*twist_max)),
+                ("swing", Value::from(*swing)),
+            ]),
+        };
+        (joint.clone(), limit)
+    });
+    let chains = options.chains.iter().map(|chain| {
+        Value::from_iter([
+            ("end", Value::from(chain.end.clone())),
+            ("distribution", Value::from(chain.distribution.to_string())),
+        ])
+    });
+    let heading = options.heading.as_ref().map(|heading| {
+        Value::from_iter([
+            ("gyro_bias", Value::from(heading.gyro_bias)),
+            ("chain", Value::from(heading.chain)),
+            ("acc_threshold", Value::from(heading.acc_threshold)),
+            ("gyr_threshold", Value::from(heading.gyr_threshold)),
+        ])
+    });
+    let retarget = options.retarget.as_ref().map(|retarget| {
+        Value::from_iter([
+            ("rig", Value::from(retarget.rig.to_string())),
+            ("joints", Value::from(retarget.joints.to_string())),
+            ("match_bones", Value::from(retarget.match_bones)),
+        ])
+    });
+    let root_motion = options.root_motion.as_ref().map(|root_motion| {
+        Value::from_iter([
+            ("sensor", Value::from(root_motion.sensor.clone())),
+            (
+                "contact_sensors",
+                Value::from(root_motion.contact_sensors.clone()),
+            ),
+            ("acc_threshold", Value::from(root_motion.acc_threshold)),
+            ("gyr_threshold", Value::from(root_motion.gyr_threshold)),
+        ])
+    });
+
+    Value::from_iter([
+        (
+            "calibration_method",
+            Value::from(calibration.method.to_string()),
+        ),
+        ("hinge_calibrations", Value::from(calibration.hinges.len())),
+        ("mapping", Value::from(mapping.to_string())),
+        ("skip_uncoupled", Value::from(options.skip_uncoupled)),
+        ("rig", Value::from(options.rig.to_string())),
+        ("frame_rate", Value::from(options.frame_rate.to_string())),
+        ("reduction", Value::from(options.reduction.to_string())),
+        (
+            "interpolation",
+            Value::from(options.interpolation.to_string()),
+        ),
+        (
+            "smoothing",
+            Value::from(options.smoothing.filter.to_string()),
+        ),
+        ("joint_smoothing", Value::from_iter(smoothing)),
+        ("limits", Value::from_iter(limits)),
+        ("chains", Value::Array(chains.collect())),
+        ("heading", Value::from(heading)),
+        ("root_motion", Value::from(root_motion)),
+        ("retarget", Value::from(retarget)),
+        ("world", Value::from(options.world.to_string())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{recording, rig},
+        limits::JointLimits,
+        to_gltf::convert,
+        tracks::FrameRate,
+    };
+
+    #[test]
+    fn provenance_extras() {
+        let mut limits = JointLimits::default();
+        limits.set(
+            "Lower".into(),
+            JointLimit::Hinge {
+                axis: Vector3::x_axis(),
+                min: 0.0,
+                max: 150.0,
+            },
+        );
+        let options = ConvertOptions {
+            frame_rate: FrameRate::Uniform(60),
+            limits,
+            provenance: Some(Provenance {
+                generator: "Axon test".into(),
+                recordings: vec!["Walk.amcx".into()],
+                calibration: None,
+            }),
+            ..Default::default()
+        };
+        let conversion = convert(
+            rig(),
+            "Test.bin",
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap();
+        let parse = |extras: &gltf::json::Extras| {
+            let raw = extras.as_ref().unwrap().get();
+            gltf::json::deserialize::from_str::<gltf::json::Value>(raw).unwrap()["provenance"]
+                .clone()
+        };
+
+        let asset = parse(&conversion.root.asset.extras);
+        assert_eq!(asset["generator"], "Axon test");
+        assert_eq!(asset["recordings"][0], "Walk.amcx");
+        assert!(asset["calibration"].is_null());
+        assert_eq!(asset["settings"]["frame_rate"], "60 fps");
+        assert_eq!(asset["settings"]["calibration_method"], "Stationary");
+        let limit = &asset["settings"]["limits"]["Lower"];
+        assert_eq!(limit["kind"], "hinge");
+        assert_eq!(limit["axis"], gltf::json::Value::from(vec![1.0, 0.0, 0.0]));
+        assert_eq!(limit["max"], 150.0);
+
+        let animation = parse(&conversion.root.animations[0].extras);
+        assert_eq!(animation["recording"], "Walk.amcx");
+
+        // the extras survive the export
+        let root = conversion.root.clone();
+        let json = root.to_string().unwrap();
+        assert!(json.contains("\"provenance\""));
+    }
+}
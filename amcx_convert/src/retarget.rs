@@ -0,0 +1,681 @@
+use std::{collections::HashMap, fmt::Display};
+
+use gltf::json::{Index, Node, Root};
+use nalgebra::{UnitQuaternion, Vector3};
+use thiserror::Error;
+
+use crate::{
+    to_gltf::{ConvertOptions, ConvertingError, Joint, Motion, NodeTree, Rig, get_node},
+    tracks,
+};
+
+/// Drives the target model through another skeleton, like the bundled Human model,
+/// which the recording, the mapping and the joint options refer to.
+///
+/// Every joint moves by the same world rotation as its source joint, which carries the motion
+/// over rest poses with different bone axes. Both rest poses are expected to be the same stance,
+/// unless `match_bones` compensates for the difference.
+#[derive(Debug, Clone)]
+pub struct Retarget {
+    /// Skeleton the recording is converted for
+    pub source: Root,
+    /// Joints of the source model driven by the recording
+    pub rig: Rig,
+    pub joints: JointMap,
+    /// Turns the target bones so that at rest they point along their source bones,
+    /// for rest poses in different stances like a T-pose and an A-pose
+    pub match_bones: bool,
+}
+
+/// Couples joints of the source skeleton with joints of the target model.
+///
+/// Source joints that are not listed drive the target joint of the same name, if there is one.
+///
+/// The text format has one joint per line:
+/// ```text
+/// # SOURCE TARGET
+/// UpperArm.R  mixamorig:RightArm
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JointMap {
+    pairs: Vec<(Joint, Joint)>,
+}
+
+impl JointMap {
+    pub fn parse(source: &str) -> Result<JointMap, JointMapError> {
+        let mut map = JointMap::default();
+        let lines = source
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+            .enumerate()
+            .filter_map(|(i, s)| (!s.is_empty()).then_some((i + 1, s)));
+
+        for (line, source) in lines {
+            let mut tokens = source.split_whitespace();
+            let (Some(source), Some(target)) = (tokens.next(), tokens.next()) else {
+                return Err(InnerJointMapError::TokenExpected("target joint".into()).at(line));
+            };
+            if let Some(found) = tokens.next() {
+                return Err(InnerJointMapError::TokenUnexpected {
+                    expected: "nothing".into(),
+                    found: found.into(),
+                }
+                .at(line));
+            }
+            if map.pairs.iter().any(|(s, _)| s == source) {
+                return Err(InnerJointMapError::SourceDuplicate(source.into()).at(line));
+            }
+            if map.pairs.iter().any(|(_, t)| t == target) {
+                return Err(InnerJointMapError::TargetDuplicate(target.into()).at(line));
+            }
+            map.pairs.push((source.into(), target.into()));
+        }
+        Ok(map)
+    }
+
+    pub fn get(&self, source: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find_map(|(s, target)| (s == source).then_some(target.as_str()))
+    }
+
+    pub fn set(&mut self, source: Joint, target: Joint) {
+        match self.pairs.iter_mut().find(|(s, _)| *s == source) {
+            Some((_, old)) => *old = target,
+            None => self.pairs.push((source, target)),
+        }
+    }
+
+    pub fn remove(&mut self, source: &str) {
+        self.pairs.retain(|(s, _)| s != source);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Target joint driven by the source joint
+    pub fn target<'a>(&'a self, source: &'a str) -> &'a str {
+        self.get(source).unwrap_or(source)
+    }
+}
+impl Display for JointMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (source, target) in &self.pairs {
+            writeln!(f, "{source} {target}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Line {line}: {inner}")]
+pub struct JointMapError {
+    line: usize,
+    inner: InnerJointMapError,
+}
+
+#[derive(Error, Debug)]
+pub enum InnerJointMapError {
+    #[error("expected {0}, but found nothing")]
+    TokenExpected(String),
+    #[error("expected {expected}, but found {found}")]
+    TokenUnexpected { expected: String, found: String },
+    #[error("duplicate source joints are not allowed: {0}")]
+    SourceDuplicate(String),
+    #[error("duplicate target joints are not allowed: {0}")]
+    TargetDuplicate(String),
+}
+impl InnerJointMapError {
+    pub fn at(self, line: usize) -> JointMapError {
+        JointMapError { line, inner: self }
+    }
+}
+
+impl Retarget {
+    /// Options of the conversion for the source skeleton
+    pub fn source_options(&self, options: &ConvertOptions) -> ConvertOptions {
+        ConvertOptions {
+            rig: self.rig.clone(),
+            retarget: None,
+            ..options.clone()
+        }
+    }
+
+    /// Motion of the `rig` joints of the target model from the motion of the source joints
+    pub(crate) fn apply(
+        &self,
+        target: &Root,
+        rig: &Rig,
+        motion: Motion,
+    ) -> Result<Motion, ConvertingError> {
+        let Motion {
+            times,
+            rotations,
+            translation,
+        } = motion;
+        let count = times.len();
+        let source = Skeleton::new(&self.source, &self.rig)?;
+        let target_skeleton = Skeleton::new(target, rig)?;
+
+        // target joint -> source joint
+        let mut coupled = HashMap::new();
+        for (joint, index) in &source.names {
+            match target_skeleton.names.get(self.joints.target(joint)) {
+                Some(target) => {
+                    coupled.insert(*target, *index);
+                }
+                None if self.joints.get(joint).is_some() => {
+                    return Err(ConvertingError::JointNotFound(
+                        self.joints.target(joint).into(),
+                    ));
+                }
+                None => {}
+            }
+        }
+        for (source_joint, _) in &self.joints.pairs {
+            if !source.names.contains_key(source_joint.as_str()) {
+                return Err(ConvertingError::JointNotFound(source_joint.clone()));
+            }
+        }
+
+        let mut offsets = HashMap::new();
+        for tree_root in &target_skeleton.tree {
+            self.bone_offsets(
+                tree_root,
+                &source,
+                &target_skeleton,
+                &coupled,
+                UnitQuaternion::identity(),
+                &mut offsets,
+            );
+        }
+
+        let mut target_rotations: HashMap<_, _> = target_skeleton
+            .rest
+            .keys()
+            .map(|index| (*index, Vec::with_capacity(count)))
+            .collect();
+        for i in 0..count {
+            let local = |index: Index<Node>| match rotations.get(&index) {
+                Some(track) => track[i],
+                None => source.rest[&index],
+            };
+            let world = source.world(&local);
+            for tree_root in &target_skeleton.tree {
+                let parent = target_skeleton.frames[&tree_root.index];
+                target_skeleton.pose(tree_root, parent, &mut |index, parent_world| {
+                    let world = match coupled.get(&index) {
+                        // the world rotation of the source joint since its rest pose
+                        Some(source_index) => {
+                            world[source_index]
+                                * source.rest_world[source_index].inverse()
+                                * offsets[&index]
+                                * target_skeleton.rest_world[&index]
+                        }
+                        None => parent_world * target_skeleton.rest[&index],
+                    };
+                    target_rotations
+                        .get_mut(&index)
+                        .unwrap()
+                        .push(parent_world.inverse() * world);
+                    world
+                });
+            }
+        }
+
+        let translation = match translation {
+            Some((source_root, translations)) => {
+                let target_root = self.translated_root(&source, &target_skeleton, source_root)?;
+                let source_rest = node_translation(get_node(&self.source, source_root)?);
+                let target_rest = node_translation(get_node(target, target_root)?);
+                let frame =
+                    target_skeleton.frames[&target_root].inverse() * source.frames[&source_root];
+                let translations = translations
+                    .into_iter()
+                    .map(|t| target_rest + frame * (t - source_rest))
+                    .collect();
+                Some((target_root, translations))
+            }
+            None => None,
+        };
+        target_rotations
+            .values_mut()
+            .for_each(|track| tracks::align_hemispheres(track));
+        Ok(Motion {
+            times,
+            rotations: target_rotations,
+            translation,
+        })
+    }
+
+    /// Rotations that turn the rest pose of the target bones onto their source bones,
+    /// in the world frame and inherited by joints without a coupled child
+    fn bone_offsets(
+        &self,
+        node: &NodeTree,
+        source: &Skeleton,
+        target: &Skeleton,
+        coupled: &HashMap<Index<Node>, Index<Node>>,
+        inherited: UnitQuaternion<f32>,
+        offsets: &mut HashMap<Index<Node>, UnitQuaternion<f32>>,
+    ) {
+        let offset = match (self.match_bones, coupled.get(&node.index)) {
+            (true, Some(source_index)) => {
+                let source_tree = source.subtree(*source_index);
+                let bone = first_coupled(node, coupled, &|child| {
+                    source_tree.is_some_and(|tree| tree.contains(child))
+                });
+                match bone {
+                    Some((target_child, source_child)) => {
+                        let source_bone =
+                            source.positions[&source_child] - source.positions[source_index];
+                        let target_bone =
+                            target.positions[&target_child] - target.positions[&node.index];
+                        UnitQuaternion::rotation_between(&target_bone, &source_bone)
+                            .unwrap_or(inherited)
+                    }
+                    None => inherited,
+                }
+            }
+            _ => inherited,
+        };
+        offsets.insert(node.index, offset);
+        for child in &node.children {
+            self.bone_offsets(child, source, target, coupled, offset, offsets);
+        }
+    }
+
+    /// Target joint translated by the root motion: the root of the tree
+    /// with the joint coupled to the source root, or the first root
+    fn translated_root(
+        &self,
+        source: &Skeleton,
+        target: &Skeleton,
+        source_root: Index<Node>,
+    ) -> Result<Index<Node>, ConvertingError> {
+        let joint = source
+            .names
+            .iter()
+            .find_map(|(name, index)| (*index == source_root).then_some(*name));
+        let coupled = joint.and_then(|joint| target.names.get(self.joints.target(joint)));
+        let tree_root = match coupled {
+            Some(index) => target
+                .tree
+                .iter()
+                .find(|tree_root| tree_root.contains(*index)),
+            None => target.tree.first(),
+        };
+        tree_root
+            .map(|tree_root| tree_root.index)
+            .ok_or(ConvertingError::NoRoot)
+    }
+}
+
+/// Nearest descendant of `node`, excluding itself, that is coupled with a source joint accepted by `accept`,
+/// with that source joint
+fn first_coupled(
+    node: &NodeTree,
+    coupled: &HashMap<Index<Node>, Index<Node>>,
+    accept: &impl Fn(Index<Node>) -> bool,
+) -> Option<(Index<Node>, Index<Node>)> {
+    let mut queue: Vec<_> = node.children.iter().collect();
+    while !queue.is_empty() {
+        if let Some(found) = queue.iter().find_map(|child| {
+            let source = coupled.get(&child.index)?;
+            accept(*source).then_some((child.index, *source))
+        }) {
+            return Some(found);
+        }
+        queue = queue.iter().flat_map(|child| &child.children).collect();
+    }
+    None
+}
+
+/// Rest pose of the joints of a rig
+struct Skeleton<'a> {
+    tree: Vec<NodeTree>,
+    names: HashMap<&'a str, Index<Node>>,
+    /// Local rest rotations
+    rest: HashMap<Index<Node>, UnitQuaternion<f32>>,
+    /// World rest rotations
+    rest_world: HashMap<Index<Node>, UnitQuaternion<f32>>,
+    /// World rest positions
+    positions: HashMap<Index<Node>, Vector3<f32>>,
+    /// World rotations of the parents of the tree roots
+    frames: HashMap<Index<Node>, UnitQuaternion<f32>>,
+}
+
+impl<'a> Skeleton<'a> {
+    fn new(root: &'a Root, rig: &Rig) -> Result<Skeleton<'a>, ConvertingError> {
+        let joints = rig.joints(root)?;
+        let tree = NodeTree::new(root, &joints)?;
+
+        let mut names = HashMap::new();
+        let mut rest = HashMap::new();
+        for index in &joints {
+            let node = get_node(root, *index)?;
+            if let Some(name) = &node.name {
+                names.insert(name.as_str(), *index);
+            }
+            rest.insert(*index, node_rotation(node));
+        }
+
+        let mut skeleton = Skeleton {
+            tree,
+            names,
+            rest,
+            rest_world: HashMap::new(),
+            positions: HashMap::new(),
+            frames: HashMap::new(),
+        };
+        for tree_root in &skeleton.tree {
+            skeleton
+                .frames
+                .insert(tree_root.index, parent_transform(root, tree_root.index)?.0);
+        }
+        skeleton.rest_world = skeleton.world(&|index| skeleton.rest[&index]);
+        for tree_root in &skeleton.tree {
+            let (_, origin) = parent_transform(root, tree_root.index)?;
+            let mut stack = vec![(tree_root, origin)];
+            while let Some((node, parent_position)) = stack.pop() {
+                let parent = match node.index == tree_root.index {
+                    true => skeleton.frames[&node.index],
+                    false => {
+                        skeleton.rest_world[&node.index] * skeleton.rest[&node.index].inverse()
+                    }
+                };
+                let translation = node_translation(get_node(root, node.index)?);
+                let position = parent_position + parent * translation;
+                skeleton.positions.insert(node.index, position);
+                stack.extend(node.children.iter().map(|child| (child, position)));
+            }
+        }
+        Ok(skeleton)
+    }
+
+    /// World rotations of every joint from their local rotations
+    fn world(
+        &self,
+        local: &impl Fn(Index<Node>) -> UnitQuaternion<f32>,
+    ) -> HashMap<Index<Node>, UnitQuaternion<f32>> {
+        let mut world = HashMap::new();
+        for tree_root in &self.tree {
+            self.pose(
+                tree_root,
+                self.frames[&tree_root.index],
+                &mut |index, parent| {
+                    let rotation = parent * local(index);
+                    world.insert(index, rotation);
+                    rotation
+                },
+            );
+        }
+        world
+    }
+
+    /// Visits the joints parents first, with the world rotation of the parent,
+    /// and passes the world rotation returned by `f` to the children
+    fn pose(
+        &self,
+        node: &NodeTree,
+        parent: UnitQuaternion<f32>,
+        f: &mut impl FnMut(Index<Node>, UnitQuaternion<f32>) -> UnitQuaternion<f32>,
+    ) {
+        let world = f(node.index, parent);
+        for child in &node.children {
+            self.pose(child, world, f);
+        }
+    }
+
+    fn subtree(&self, index: Index<Node>) -> Option<&NodeTree> {
+        fn find(node: &NodeTree, index: Index<Node>) -> Option<&NodeTree> {
+            match node.index == index {
+                true => Some(node),
+                false => node.children.iter().find_map(|child| find(child, index)),
+            }
+        }
+        self.tree
+            .iter()
+            .find_map(|tree_root| find(tree_root, index))
+    }
+}
+
+/// World rotation and position of the parent of the node, from its ancestors in the scene
+fn parent_transform(
+    root: &Root,
+    index: Index<Node>,
+) -> Result<(UnitQuaternion<f32>, Vector3<f32>), ConvertingError> {
+    let mut ancestors = Vec::new();
+    let mut child = index;
+    while let Some(parent) = root
+        .nodes
+        .iter()
+        .position(|node| node.children.iter().flatten().any(|c| *c == child))
+    {
+        let parent = Index::new(parent as u32);
+        // guards against cyclic hierarchies in malformed files
+        if ancestors.contains(&parent) || parent == index {
+            break;
+        }
+        ancestors.push(parent);
+        child = parent;
+    }
+
+    let mut rotation = UnitQuaternion::identity();
+    let mut position = Vector3::zeros();
+    for ancestor in ancestors.into_iter().rev() {
+        let node = get_node(root, ancestor)?;
+        position += rotation * node_translation(node);
+        rotation *= node_rotation(node);
+    }
+    Ok((rotation, position))
+}
+
+fn node_rotation(node: &Node) -> UnitQuaternion<f32> {
+    node.rotation.map_or(UnitQuaternion::identity(), |r| {
+        UnitQuaternion::from_quaternion(r.0.into())
+    })
+}
+
+fn node_translation(node: &Node) -> Vector3<f32> {
+    Vector3::from(node.translation.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        angles::{self, AngleOptions},
+        calibration::Calibration,
+        fixtures::{recording, rig, try_convert_with},
+        mapping::Mapping,
+        to_gltf::convert,
+    };
+
+    #[test]
+    fn joint_map_parse() {
+        let map = JointMap::parse("# source target\nUpper Arm\n\nLower Forearm # elbow\n").unwrap();
+        assert_eq!(map.target("Upper"), "Arm");
+        assert_eq!(map.target("Lower"), "Forearm");
+        assert_eq!(map.target("Hand"), "Hand");
+        assert_eq!(JointMap::parse(&map.to_string()).unwrap(), map);
+
+        assert!(JointMap::parse("Upper").is_err());
+        assert!(JointMap::parse("Upper Arm Hand").is_err());
+        assert!(JointMap::parse("Upper Arm\nUpper Forearm").is_err());
+        assert!(JointMap::parse("Upper Arm\nLower Arm").is_err());
+    }
+
+    /// Target with other names, bone axes and an A-pose, under a node turning it Z-up
+    const TARGET: &str = r#"{
+        "asset": { "version": "2.0" },
+        "nodes": [
+            { "name": "Armature", "rotation": [-0.7071068, 0.0, 0.0, 0.7071068], "children": [1] },
+            { "name": "Arm", "rotation": [0.0, 0.0, -0.3826834, 0.9238795], "children": [2] },
+            { "name": "Forearm", "translation": [0.0, 2.0, 0.0], "rotation": [0.5, 0.5, 0.5, 0.5] }
+        ],
+        "skins": [{ "joints": [1, 2] }]
+    }"#;
+
+    /// Retargets from the test rig, with its lower joint along the x axis
+    fn retarget(match_bones: bool) -> Retarget {
+        let mut source = rig();
+        source.nodes[1].translation = Some([1.0, 0.0, 0.0]);
+        Retarget {
+            source,
+            rig: Rig::FirstSkin,
+            joints: JointMap::parse("Upper Arm\nLower Forearm").unwrap(),
+            match_bones,
+        }
+    }
+
+    #[test]
+    fn retarget_rest_compensation() {
+        let source = retarget(false).source;
+        let target = Root::from_str(TARGET).unwrap();
+        let node = |_: &Root, index: u32| gltf::json::Index::new(index);
+        let rest = |root: &Root, index: u32| {
+            let r = root.nodes[index as usize].rotation.unwrap().0;
+            UnitQuaternion::from_quaternion(r.into())
+        };
+        let lower_rest = rest(&source, 1);
+        let moved = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.5);
+        let bent = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.3);
+        let motion = || Motion {
+            times: vec![0.0, 1.0],
+            rotations: [
+                (node(&source, 0), vec![UnitQuaternion::identity(), moved]),
+                (node(&source, 1), vec![lower_rest, lower_rest * bent]),
+            ]
+            .into(),
+            translation: None,
+        };
+
+        // without matching bones the joints move by the world rotations of their source joints
+        let retargeted = retarget(false)
+            .apply(&target, &Rig::FirstSkin, motion())
+            .unwrap();
+        let armature = rest(&target, 0);
+        let (arm_rest, forearm_rest) = (rest(&target, 1), rest(&target, 2));
+        for i in 0..2 {
+            let arm = retargeted.rotations[&node(&target, 1)][i];
+            let forearm = retargeted.rotations[&node(&target, 2)][i];
+            let upper = motion().rotations[&node(&source, 0)][i];
+            let lower = motion().rotations[&node(&source, 1)][i];
+
+            let arm_delta = armature * arm * (armature * arm_rest).inverse();
+            let forearm_delta =
+                armature * arm * forearm * (armature * arm_rest * forearm_rest).inverse();
+            assert!(arm_delta.angle_to(&upper) < 1e-4);
+            assert!(forearm_delta.angle_to(&(upper * lower * lower_rest.inverse())) < 1e-4);
+        }
+
+        // matching bones turns the forearm bone from the A-pose onto the source bone
+        let retargeted = retarget(true)
+            .apply(&target, &Rig::FirstSkin, motion())
+            .unwrap();
+        let bone = Vector3::new(0.0, 2.0, 0.0);
+        for (i, upper) in [UnitQuaternion::identity(), moved].iter().enumerate() {
+            let arm = retargeted.rotations[&node(&target, 1)][i];
+            let direction = (armature * arm * bone).normalize();
+            assert!((direction - upper * Vector3::x()).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn convert_retargeted() {
+        let options = ConvertOptions {
+            retarget: Some(retarget(true)),
+            ..Default::default()
+        };
+        let target = Root::from_str(TARGET).unwrap();
+        let conversion = convert(
+            target,
+            "Test.bin",
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap();
+        let mut nodes: Vec<_> = conversion.root.animations[0]
+            .channels
+            .iter()
+            .map(|channel| channel.target.node.value())
+            .collect();
+        nodes.sort();
+        assert_eq!(nodes, [1, 2]);
+
+        let unknown = ConvertOptions {
+            retarget: Some(Retarget {
+                joints: JointMap::parse("Upper Shoulder").unwrap(),
+                ..retarget(false)
+            }),
+            ..Default::default()
+        };
+        let result = try_convert_with(
+            Root::from_str(TARGET).unwrap(),
+            &recording(),
+            &Calibration::default(),
+            &unknown,
+        );
+        assert!(
+            matches!(result, Err(ConvertingError::JointNotFound(joint)) if joint == "Shoulder")
+        );
+    }
+
+    #[test]
+    fn angles_retargeted() {
+        let angles = |gltf: &Root, options: &ConvertOptions| {
+            angles::convert(
+                gltf,
+                &recording(),
+                &Calibration::default(),
+                &Mapping::default(),
+                options,
+                &AngleOptions::default(),
+            )
+        };
+        let retarget = retarget(true);
+        let target = Root::from_str(TARGET).unwrap();
+        let options = ConvertOptions {
+            rig: Rig::default(),
+            retarget: Some(retarget.clone()),
+            ..Default::default()
+        };
+
+        // angles of a retargeted target are those of the source skeleton
+        let (report, _) = angles(&target, &options).unwrap();
+        let joints: Vec<_> = report.joints.iter().map(|j| j.joint.as_str()).collect();
+        assert_eq!(joints, ["Upper", "Lower"]);
+        let (source, _) = angles(&retarget.source, &retarget.source_options(&options)).unwrap();
+        assert_eq!(report, source);
+
+        // the target has none of the source joints without the retarget
+        let unretargeted = ConvertOptions {
+            rig: retarget.rig.clone(),
+            ..Default::default()
+        };
+        assert!(angles(&target, &unretargeted).is_err());
+    }
+
+    #[test]
+    fn retarget_without_root() {
+        let target = Root::from_str(
+            r#"{ "asset": { "version": "2.0" }, "nodes": [{ "name": "Arm" }], "skins": [{ "joints": [] }] }"#,
+        )
+        .unwrap();
+        let motion = Motion {
+            times: vec![0.0],
+            rotations: HashMap::new(),
+            translation: Some((Index::new(0), vec![Vector3::zeros()])),
+        };
+        let retarget = Retarget {
+            joints: JointMap::default(),
+            ..retarget(false)
+        };
+        let result = retarget.apply(&target, &Rig::FirstSkin, motion);
+        assert!(matches!(result, Err(ConvertingError::NoRoot)));
+    }
+}
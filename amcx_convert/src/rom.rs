@@ -0,0 +1,231 @@
+use std::fmt::{Display, Write};
+
+/// Detection of repetitions in a single joint angle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RepetitionOptions {
+    /// Smallest rise in degrees from the surrounding valleys to the peak of a repetition
+    pub min_amplitude: f32,
+    /// Peaks are minima of the angle, like for extension
+    pub inverted: bool,
+}
+impl Default for RepetitionOptions {
+    fn default() -> Self {
+        RepetitionOptions {
+            min_amplitude: 10.0,
+            inverted: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Repetition {
+    /// Time in seconds of the valley before the peak
+    pub start: f32,
+    /// Time in seconds of the valley after the peak
+    pub end: f32,
+    pub peak_time: f32,
+    /// Peak angle in degrees
+    pub peak: f32,
+}
+impl Repetition {
+    pub fn duration(&self) -> f32 {
+        self.end - self.start
+    }
+}
+
+/// Range of motion of a single joint angle, in degrees
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RangeOfMotion {
+    pub min: f32,
+    pub max: f32,
+    /// Mean of the repetition peaks, `None` without repetitions
+    pub mean_peak: Option<f32>,
+    pub repetitions: Vec<Repetition>,
+}
+impl RangeOfMotion {
+    pub fn range(&self) -> f32 {
+        self.max - self.min
+    }
+
+    /// Summary row followed by one row per repetition
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("Min,Max,Range,MeanPeak,Repetitions\n");
+        let mean_peak = self.mean_peak.map_or(String::new(), |p| format!("{p:.3}"));
+        writeln!(
+            csv,
+            "{:.3},{:.3},{:.3},{mean_peak},{}",
+            self.min,
+            self.max,
+            self.range(),
+            self.repetitions.len()
+        )
+        .unwrap();
+
+        csv.push_str("\nRepetition,Start,End,Duration,PeakTime,Peak\n");
+        for (i, rep) in self.repetitions.iter().enumerate() {
+            writeln!(
+                csv,
+                "{},{:.4},{:.4},{:.4},{:.4},{:.3}",
+                i + 1,
+                rep.start,
+                rep.end,
+                rep.duration(),
+                rep.peak_time,
+                rep.peak
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+impl Display for RangeOfMotion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Range {:.1}° to {:.1}° ({:.1}°), {} repetitions",
+            self.min,
+            self.max,
+            self.range(),
+            self.repetitions.len()
+        )?;
+        if let Some(mean_peak) = self.mean_peak {
+            let count = self.repetitions.len() as f32;
+            let mean_duration = self
+                .repetitions
+                .iter()
+                .map(Repetition::duration)
+                .sum::<f32>()
+                / count;
+            write!(
+                f,
+                ", mean peak {mean_peak:.1}°, mean duration {mean_duration:.2} s"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Range of motion of `angles` in degrees sampled at `times` in seconds
+///
+/// A repetition rises above the middle of the range and falls back below it,
+/// with some hysteresis against noise.
+pub fn analyze(times: &[f32], angles: &[f32], options: &RepetitionOptions) -> RangeOfMotion {
+    let len = times.len().min(angles.len());
+    if len == 0 {
+        return RangeOfMotion::default();
+    }
+    let sign = if options.inverted { -1.0 } else { 1.0 };
+    let values: Vec<_> = angles[..len].iter().map(|a| a * sign).collect();
+
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let (min_angle, max_angle) = match options.inverted {
+        true => (-max, -min),
+        false => (min, max),
+    };
+    let mut rom = RangeOfMotion {
+        min: min_angle,
+        max: max_angle,
+        ..Default::default()
+    };
+    if max - min < options.min_amplitude {
+        return rom;
+    }
+
+    // samples above the middle of the range
+    let middle = (min + max) / 2.0;
+    let hysteresis = options.min_amplitude / 4.0;
+    let mut crests = Vec::new();
+    let mut above = None;
+    for (i, v) in values.iter().enumerate() {
+        match above {
+            None if *v > middle + hysteresis => above = Some(i),
+            Some(start) if *v < middle - hysteresis => {
+                crests.push(start..i);
+                above = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = above {
+        crests.push(start..len);
+    }
+
+    let lowest = |from: usize, to: usize| {
+        (from..to.max(from + 1).min(len)).min_by(|a, b| values[*a].total_cmp(&values[*b]))
+    };
+    for (i, crest) in crests.iter().enumerate() {
+        let valley_from = i.checked_sub(1).map_or(0, |prev| crests[prev].end);
+        let valley_to = crests.get(i + 1).map_or(len, |next| next.start);
+        let (Some(start), Some(end)) = (
+            lowest(valley_from, crest.start),
+            lowest(crest.end, valley_to),
+        ) else {
+            continue;
+        };
+        let Some(peak) = crest
+            .clone()
+            .max_by(|a, b| values[*a].total_cmp(&values[*b]))
+        else {
+            continue;
+        };
+        // repetitions cut off by the recording don't rise from both valleys
+        if values[peak] - values[start].max(values[end]) < options.min_amplitude {
+            continue;
+        }
+        rom.repetitions.push(Repetition {
+            start: times[start],
+            end: times[end],
+            peak_time: times[peak],
+            peak: values[peak] * sign,
+        });
+    }
+
+    if !rom.repetitions.is_empty() {
+        let sum: f32 = rom.repetitions.iter().map(|rep| rep.peak).sum();
+        rom.mean_peak = Some(sum / rom.repetitions.len() as f32);
+    }
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repetition_detection() {
+        // three flexions to 60° with a little tremor, two seconds each
+        let times: Vec<_> = (0..=600).map(|i| i as f32 * 0.01).collect();
+        let angles: Vec<_> = times
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let tremor = if i % 2 == 0 { 0.5 } else { -0.5 };
+                30.0 * (1.0 - (std::f32::consts::PI * t).cos()) + tremor
+            })
+            .collect();
+
+        let rom = analyze(&times, &angles, &RepetitionOptions::default());
+        assert_eq!(rom.repetitions.len(), 3, "{rom:?}");
+        assert!((rom.max - 60.5).abs() < 0.1 && (rom.min + 0.5).abs() < 0.1);
+        assert!((rom.mean_peak.unwrap() - 60.0).abs() < 1.0);
+        for (rep, peak_time) in rom.repetitions.iter().zip([1.0, 3.0, 5.0]) {
+            assert!((rep.peak_time - peak_time).abs() < 0.05, "{rep:?}");
+            assert!((rep.duration() - 2.0).abs() < 0.05, "{rep:?}");
+        }
+        assert_eq!(rom.to_csv().lines().count(), 2 + 1 + 1 + 3);
+
+        let extension: Vec<_> = angles.iter().map(|a| -a).collect();
+        let inverted = RepetitionOptions {
+            inverted: true,
+            ..Default::default()
+        };
+        let rom = analyze(&times, &extension, &inverted);
+        assert_eq!(rom.repetitions.len(), 3);
+        assert!(rom.repetitions.iter().all(|rep| rep.peak < -59.0));
+
+        let still = vec![1.0; times.len()];
+        let rom = analyze(&times, &still, &RepetitionOptions::default());
+        assert!(rom.repetitions.is_empty() && rom.mean_peak.is_none());
+    }
+}
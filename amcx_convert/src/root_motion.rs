@@ -0,0 +1,243 @@
+use amcx_core::{Record, Sensor};
+use nalgebra::{UnitQuaternion, Vector3};
+
+/// Standard gravity in m/s², accelerometers measure in g
+pub const GRAVITY: f32 = 9.80665;
+
+/// Estimation of the root translation by integrating the acceleration of one sensor
+///
+/// Drift is removed at zero-velocity instants, when the contact sensors are still,
+/// so the integrated sensor should be still whenever they are, like a foot on the ground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootMotion {
+    /// Sensor whose acceleration is integrated, on the pelvis or a foot
+    pub sensor: Sensor,
+    /// Sensors that detect foot contact, the integrated sensor itself if empty
+    pub contact_sensors: Vec<Sensor>,
+    /// Largest deviation of the acceleration norm from 1 g in a still sample
+    pub acc_threshold: f32,
+    /// Largest angular speed in rad/s in a still sample
+    pub gyr_threshold: f32,
+}
+impl RootMotion {
+    pub fn new(sensor: Sensor) -> RootMotion {
+        RootMotion {
+            sensor,
+            contact_sensors: Vec::new(),
+            acc_threshold: 0.05,
+            gyr_threshold: 0.3,
+        }
+    }
+
+    /// Whether the sensor is still in each sample
+    pub fn stillness(&self, stream: &[Record]) -> Vec<bool> {
+        stillness(stream, self.acc_threshold, self.gyr_threshold)
+    }
+}
+
+/// Whether each sample measures only gravity, within `acc_threshold` g and `gyr_threshold` rad/s
+pub fn stillness(stream: &[Record], acc_threshold: f32, gyr_threshold: f32) -> Vec<bool> {
+    stream
+        .iter()
+        .map(|record| {
+            let acc = Vector3::from(record.sample.acc);
+            let gyr = Vector3::from(record.sample.gyr);
+            (acc.norm() - 1.0).abs() <= acc_threshold && gyr.norm() <= gyr_threshold
+        })
+        .collect()
+}
+
+/// Positions in meters in the AHRS world frame, relative to the first sample
+///
+/// `orientations` are the AHRS outputs of the sensor. The recording is assumed to start at rest,
+/// the gravity measured there levels the orientations, the velocity is forced to zero
+/// in `still` samples and the drift accumulated while moving is spread over the movement.
+pub fn integrate(
+    stream: &[Record],
+    orientations: &[UnitQuaternion<f32>],
+    still: &[bool],
+) -> Vec<Vector3<f32>> {
+    let len = stream.len().min(orientations.len()).min(still.len());
+    if len == 0 {
+        return Vec::new();
+    }
+    let times: Vec<_> = stream[..len]
+        .iter()
+        .map(|record| record.timestamp.as_secs_f32())
+        .collect();
+    let level = level(&stream[..len], &orientations[..len], &still[..len]);
+    let acc: Vec<_> = (0..len)
+        .map(|i| {
+            let world = level * orientations[i] * Vector3::from(stream[i].sample.acc);
+            (world - Vector3::z()) * GRAVITY
+        })
+        .collect();
+
+    let mut velocity = vec![Vector3::zeros(); len];
+    let mut moving_since = None;
+    for i in 1..len {
+        let dt = times[i] - times[i - 1];
+        if still[i] {
+            if let Some(start) = moving_since.take() {
+                // the segment starts at the last still sample, where the velocity is zero
+                let error = velocity[i - 1];
+                remove_drift(&mut velocity[start - 1..i], &times[start - 1..i], error);
+            }
+            continue;
+        }
+        if moving_since.is_none() {
+            moving_since = Some(i);
+        }
+        velocity[i] = velocity[i - 1] + (acc[i - 1] + acc[i]) * 0.5 * dt;
+    }
+
+    let mut position = vec![Vector3::zeros(); len];
+    for i in 1..len {
+        let dt = times[i] - times[i - 1];
+        position[i] = position[i - 1] + (velocity[i - 1] + velocity[i]) * 0.5 * dt;
+    }
+    position
+}
+
+/// Rotation turning the gravity of the initial still samples onto the z axis
+///
+/// The AHRS starts from the identity whatever the tilt of the sensor,
+/// so its frame is only level if the sensor was.
+fn level(
+    stream: &[Record],
+    orientations: &[UnitQuaternion<f32>],
+    still: &[bool],
+) -> UnitQuaternion<f32> {
+    let initial = still.iter().take_while(|still| **still).count().max(1);
+    let gravity: Vector3<f32> = stream[..initial]
+        .iter()
+        .zip(orientations)
+        .map(|(record, orientation)| orientation * Vector3::from(record.sample.acc))
+        .sum();
+    UnitQuaternion::rotation_between(&gravity, &Vector3::z()).unwrap_or_else(|| {
+        // upside down
+        UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI)
+    })
+}
+
+/// The velocity error grows from zero at the start of the movement to `error` at its end
+fn remove_drift(velocity: &mut [Vector3<f32>], times: &[f32], error: Vector3<f32>) {
+    let (Some(start), Some(end)) = (times.first(), times.last()) else {
+        return;
+    };
+    let duration = end - start;
+    for (v, t) in velocity.iter_mut().zip(times) {
+        let share = match duration > 0.0 {
+            true => (t - start) / duration,
+            false => 1.0,
+        };
+        *v -= error * share;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use amcx_core::Sample;
+    use gltf::{animation::Property, json::validation::Checked};
+
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        fixtures::{recording, rig, try_convert_with},
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, ConvertingError, convert},
+    };
+
+    /// Still, accelerating along x, braking and still again, with an accelerometer bias while moving
+    fn walking_step() -> Vec<Record> {
+        (0..100)
+            .map(|i| {
+                let acc = match i {
+                    20..40 => [0.55, 0.0, 1.0],
+                    40..60 => [-0.45, 0.0, 1.0],
+                    _ => [0.0, 0.0, 1.0],
+                };
+                Record {
+                    timestamp: Duration::from_millis(10 * i as u64),
+                    sample: Sample { acc, gyr: [0.0; 3] },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn root_motion_zero_velocity_update() {
+        let stream = walking_step();
+        let orientations = vec![UnitQuaternion::identity(); stream.len()];
+        let still = RootMotion::new("Foot".into()).stillness(&stream);
+        assert!(still[..20].iter().all(|s| *s) && still[20..60].iter().all(|s| !s));
+
+        let corrected = integrate(&stream, &orientations, &still);
+        let drifting = integrate(&stream, &orientations, &vec![false; stream.len()]);
+        // 0.5 g for 0.2 s, then braking for 0.2 s
+        let expected = 0.5 * GRAVITY * 0.2 * 0.2;
+        let last = corrected.last().unwrap();
+        assert!((last.x - expected).abs() < 0.02, "{last}");
+        assert!((corrected[70] - last).norm() < 1e-6);
+        assert!((drifting.last().unwrap().x - expected).abs() > 0.05);
+    }
+
+    #[test]
+    fn root_motion_tilted_start() {
+        // the sensor leans forward, the AHRS starts from the identity regardless
+        let tilt = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.3);
+        let stream: Vec<_> = walking_step()
+            .into_iter()
+            .map(|mut record| {
+                record.sample.acc = (tilt.inverse() * Vector3::from(record.sample.acc)).into();
+                record
+            })
+            .collect();
+        let orientations = vec![UnitQuaternion::identity(); stream.len()];
+        let still = RootMotion::new("Foot".into()).stillness(&stream);
+
+        let moved = integrate(&stream, &orientations, &still);
+        let expected = 0.5 * GRAVITY * 0.2 * 0.2;
+        let last = moved.last().unwrap();
+        assert!((last.x - expected).abs() < 0.02, "{last}");
+        assert!(last.z.abs() < 0.02, "{last}");
+    }
+
+    #[test]
+    fn convert_root_motion() {
+        let options = ConvertOptions {
+            root_motion: Some(RootMotion::new("Lower".into())),
+            ..Default::default()
+        };
+        let conversion = convert(
+            rig(),
+            "Test.bin",
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap();
+        let animation = conversion.root.animations.last().unwrap();
+        let translations: Vec<_> = animation
+            .channels
+            .iter()
+            .filter(|channel| channel.target.path == Checked::Valid(Property::Translation))
+            .collect();
+        // the root of the rig carries the translation
+        assert_eq!(translations.len(), 1);
+        assert_eq!(translations[0].target.node.value(), 0);
+
+        let options = ConvertOptions {
+            root_motion: Some(RootMotion {
+                contact_sensors: vec!["Foot".into()],
+                ..RootMotion::new("Upper".into())
+            }),
+            ..Default::default()
+        };
+        let result = try_convert_with(rig(), &recording(), &Calibration::default(), &options);
+        assert!(matches!(result, Err(ConvertingError::RootMotionSensorMissing(s)) if s == "Foot"));
+    }
+}
@@ -0,0 +1,544 @@
+use std::{collections::HashMap, fmt::Display};
+
+use gltf::json::{Index, Node, Root, mesh::Semantic, validation::Checked};
+use nalgebra::{Matrix3, Matrix4, UnitQuaternion, Vector3};
+use thiserror::Error;
+
+use crate::to_gltf::{ConvertingError, Joint, NodeTree, Rig, get_node};
+
+/// Proportions of the subject, which the skeleton of the target model is scaled to before conversion.
+///
+/// The height scales the whole skeleton, segments then set the distance between two joints.
+/// The text format has one measurement per line, in metres:
+/// ```text
+/// height 1.82
+/// # START END LENGTH
+/// ArmLowR Hand.R 0.27
+/// ```
+/// where the end of a segment is a descendant of the start joint,
+/// and every bone in between is scaled alike.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BodyMeasurements {
+    pub height: Option<f32>,
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: Joint,
+    pub end: Joint,
+    pub length: f32,
+}
+
+impl BodyMeasurements {
+    pub const HEIGHT: &str = "height";
+
+    pub fn parse(source: &str) -> Result<BodyMeasurements, MeasurementsError> {
+        let mut measurements = BodyMeasurements::default();
+        let lines = source
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+            .enumerate()
+            .filter_map(|(i, s)| (!s.is_empty()).then_some((i + 1, s)));
+
+        for (line, source) in lines {
+            measurements
+                .parse_line(source)
+                .map_err(|err| err.at(line))?;
+        }
+        Ok(measurements)
+    }
+
+    fn parse_line(&mut self, source: &str) -> Result<(), InnerMeasurementsError> {
+        let tokens: Vec<_> = source.split_whitespace().collect();
+        match tokens.as_slice() {
+            [BodyMeasurements::HEIGHT, height] => {
+                if self.height.is_some() {
+                    return Err(InnerMeasurementsError::HeightDuplicate);
+                }
+                self.height = Some(parse_length(height)?);
+            }
+            [BodyMeasurements::HEIGHT] => {
+                return Err(InnerMeasurementsError::TokenExpected("height".into()));
+            }
+            [start, end, length] => {
+                if self.segment(start, end).is_some() {
+                    return Err(InnerMeasurementsError::SegmentDuplicate(
+                        start.to_string(),
+                        end.to_string(),
+                    ));
+                }
+                self.segments.push(Segment {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                    length: parse_length(length)?,
+                });
+            }
+            [_] | [_, _] => return Err(InnerMeasurementsError::TokenExpected("length".into())),
+            [_, _, _, found, ..] => {
+                return Err(InnerMeasurementsError::TokenUnexpected {
+                    expected: "nothing".into(),
+                    found: found.to_string(),
+                });
+            }
+            [] => {}
+        }
+        Ok(())
+    }
+
+    pub fn segment(&self, start: &str, end: &str) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .find(|segment| segment.start == start && segment.end == end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.height.is_none() && self.segments.is_empty()
+    }
+}
+impl Display for BodyMeasurements {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(height) = self.height {
+            writeln!(f, "{} {height}", BodyMeasurements::HEIGHT)?;
+        }
+        for segment in &self.segments {
+            writeln!(f, "{} {} {}", segment.start, segment.end, segment.length)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_length(source: &str) -> Result<f32, InnerMeasurementsError> {
+    match source.parse::<f32>() {
+        Ok(length) if length > 0.0 && length.is_finite() => Ok(length),
+        _ => Err(InnerMeasurementsError::InvalidLength(source.into())),
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Line {line}: {inner}")]
+pub struct MeasurementsError {
+    line: usize,
+    inner: InnerMeasurementsError,
+}
+
+#[derive(Error, Debug)]
+pub enum InnerMeasurementsError {
+    #[error("expected {0}, but found nothing")]
+    TokenExpected(String),
+    #[error("expected {expected}, but found {found}")]
+    TokenUnexpected { expected: String, found: String },
+    #[error("height is given twice")]
+    HeightDuplicate,
+    #[error("duplicate segments are not allowed: {0} {1}")]
+    SegmentDuplicate(String, String),
+    #[error("invalid length {0}, expected a positive number of metres")]
+    InvalidLength(String),
+}
+impl InnerMeasurementsError {
+    pub fn at(self, line: usize) -> MeasurementsError {
+        MeasurementsError { line, inner: self }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ScalingError {
+    #[error(transparent)]
+    Converting(#[from] ConvertingError),
+    #[error("Joint {end} is not below {start}")]
+    NotDescendant { start: String, end: String },
+    #[error("Joints {0} and {1} are at the same place, so their segment has no length")]
+    SegmentDegenerate(String, String),
+    #[error("Target model has no extent to measure its height")]
+    HeightUndetermined,
+    #[error("Inverse bind matrices of skin {0} are not readable")]
+    InverseBindMatricesInvalid(usize),
+    #[error("Buffer {0} is not loaded")]
+    BufferNotLoaded(String),
+}
+
+/// Scales the translations of the `rig` joints and the inverse bind matrices of every skin,
+/// so that the skinned meshes stretch along with their bones.
+///
+/// The height is compared with the extent of the skinned meshes along the glTF up axis Y.
+/// The buffers with the inverse bind matrices are changed in place.
+pub fn scale(
+    root: &mut Root,
+    bins: &mut [(String, Vec<u8>)],
+    rig: &Rig,
+    measurements: &BodyMeasurements,
+) -> Result<(), ScalingError> {
+    if measurements.is_empty() {
+        return Ok(());
+    }
+    let joints = rig.joints(root)?;
+    let mut parents = HashMap::new();
+    for tree_root in NodeTree::new(root, &joints)? {
+        tree_root.for_each_bf(&mut |node| {
+            for child in &node.children {
+                parents.insert(child.index, node.index);
+            }
+        });
+    }
+    let find = |joint: &str| {
+        joints
+            .iter()
+            .find(|index| root.get(**index).and_then(|node| node.name.as_deref()) == Some(joint))
+            .copied()
+            .ok_or_else(|| ConvertingError::JointNotFound(joint.into()))
+    };
+    let positions = rest_positions(root, &joints, &parents)?;
+
+    let height_factor = match measurements.height {
+        Some(height) => height / model_height(root, &joints)?,
+        None => 1.0,
+    };
+    let mut factors: HashMap<_, _> = joints.iter().map(|index| (*index, height_factor)).collect();
+    // joints whose bone to the given child is stretched by the factor, on top of the height
+    let mut stretches = HashMap::new();
+    for segment in &measurements.segments {
+        let (start, end) = (find(&segment.start)?, find(&segment.end)?);
+        let mut path = Vec::new();
+        let mut node = end;
+        while node != start {
+            path.push(node);
+            node = *parents
+                .get(&node)
+                .ok_or_else(|| ScalingError::NotDescendant {
+                    start: segment.start.clone(),
+                    end: segment.end.clone(),
+                })?;
+        }
+        let length = (positions[&end] - positions[&start]).norm();
+        if length < f32::EPSILON {
+            return Err(ScalingError::SegmentDegenerate(
+                segment.start.clone(),
+                segment.end.clone(),
+            ));
+        }
+        let factor = segment.length / length;
+        for node in path {
+            factors.insert(node, factor);
+            stretches.insert(parents[&node], (node, factor / height_factor));
+        }
+    }
+
+    for index in &joints {
+        let node = root
+            .nodes
+            .get_mut(index.value())
+            .ok_or(ConvertingError::NodeMissing(index.value()))?;
+        if let Some(translation) = node.translation.as_mut() {
+            translation.iter_mut().for_each(|t| *t *= factors[index]);
+        }
+    }
+
+    for skin in 0..root.skins.len() {
+        let Some(mut matrices) = read_matrices(root, bins, skin)? else {
+            continue;
+        };
+        let skin_joints = root.skins[skin].joints.clone();
+        let bind_positions: HashMap<_, _> = skin_joints
+            .iter()
+            .zip(&matrices)
+            .filter_map(|(index, ibm)| Some((*index, bind_position(ibm)?)))
+            .collect();
+        for (index, ibm) in skin_joints.iter().zip(matrices.iter_mut()) {
+            let Some(position) = bind_positions
+                .get(index)
+                .filter(|_| factors.contains_key(index))
+            else {
+                continue;
+            };
+            let mut scaling = Matrix3::identity();
+            let bone = stretches.get(index).and_then(|(child, stretch)| {
+                let bone = (bind_positions.get(child)? - position).try_normalize(f32::EPSILON)?;
+                Some((bone, stretch))
+            });
+            if let Some((bone, stretch)) = bone {
+                scaling += (stretch - 1.0) * bone * bone.transpose();
+            }
+            // vertices bound to the joint scale around it and follow its new position
+            let local = Matrix4::new_translation(position)
+                * (scaling * height_factor).to_homogeneous()
+                * Matrix4::new_translation(&-position);
+            *ibm *= local;
+        }
+        write_matrices(root, bins, skin, &matrices)?;
+    }
+    Ok(())
+}
+
+/// Rest positions of the joints relative to the topmost of the `parents`
+fn rest_positions(
+    root: &Root,
+    joints: &[Index<Node>],
+    parents: &HashMap<Index<Node>, Index<Node>>,
+) -> Result<HashMap<Index<Node>, Vector3<f32>>, ConvertingError> {
+    let mut positions = HashMap::new();
+    let translation = |node: &Node| Vector3::from(node.translation.unwrap_or_default());
+    for index in joints {
+        let mut position = translation(get_node(root, *index)?);
+        let mut node = *index;
+        let mut ancestors = vec![node];
+        while let Some(parent) = parents.get(&node) {
+            // guards against cyclic hierarchies in malformed files
+            if ancestors.contains(parent) {
+                break;
+            }
+            ancestors.push(*parent);
+            let rest = get_node(root, *parent)?;
+            position = translation(rest) + rotation(rest) * position;
+            node = *parent;
+        }
+        positions.insert(*index, position);
+    }
+    Ok(positions)
+}
+
+fn rotation(node: &Node) -> UnitQuaternion<f32> {
+    node.rotation.map_or(UnitQuaternion::identity(), |r| {
+        UnitQuaternion::from_quaternion(r.0.into())
+    })
+}
+
+/// Extent of the skinned meshes along Y, or of the joints in the scene without mesh bounds
+fn model_height(root: &Root, joints: &[Index<Node>]) -> Result<f32, ScalingError> {
+    // glTF models are Y up
+    let up = 1;
+    let bounds = root
+        .nodes
+        .iter()
+        .filter(|node| node.skin.is_some())
+        .filter_map(|node| root.get(node.mesh?))
+        .flat_map(|mesh| &mesh.primitives)
+        .filter_map(|primitive| {
+            let accessor = root.get(
+                *primitive
+                    .attributes
+                    .get(&Checked::Valid(Semantic::Positions))?,
+            )?;
+            let bound = |value: &Option<gltf::json::Value>| value.as_ref()?.get(up)?.as_f64();
+            Some((bound(&accessor.min)?, bound(&accessor.max)?))
+        })
+        .reduce(|(min, max), (lo, hi)| (min.min(lo), max.max(hi)));
+    let height = match bounds {
+        Some((min, max)) => (max - min) as f32,
+        None => {
+            // the joints are placed through every ancestor, like an armature turning them Y up
+            let parents: HashMap<_, _> = root
+                .nodes
+                .iter()
+                .enumerate()
+                .flat_map(|(index, node)| {
+                    let parent = Index::new(index as u32);
+                    node.children
+                        .iter()
+                        .flatten()
+                        .map(move |child| (*child, parent))
+                })
+                .collect();
+            let (min, max) = rest_positions(root, joints, &parents)?.values().fold(
+                (f32::INFINITY, f32::NEG_INFINITY),
+                |(min, max), position| (min.min(position[up]), max.max(position[up])),
+            );
+            max - min
+        }
+    };
+    match height > f32::EPSILON {
+        true => Ok(height),
+        false => Err(ScalingError::HeightUndetermined),
+    }
+}
+
+fn bind_position(ibm: &Matrix4<f32>) -> Option<Vector3<f32>> {
+    let bind = ibm.try_inverse()?;
+    Some(bind.fixed_view::<3, 1>(0, 3).into_owned())
+}
+
+/// Byte ranges of the inverse bind matrices of the skin in their buffer file
+fn matrix_layout(root: &Root, skin: usize) -> Result<Option<(String, Vec<usize>)>, ScalingError> {
+    let invalid = || ScalingError::InverseBindMatricesInvalid(skin);
+    let Some(accessor) = root.skins[skin].inverse_bind_matrices else {
+        return Ok(None);
+    };
+    let accessor = root.get(accessor).ok_or_else(invalid)?;
+    let view = root
+        .get(accessor.buffer_view.ok_or_else(invalid)?)
+        .ok_or_else(invalid)?;
+    let buffer = root.get(view.buffer).ok_or_else(invalid)?;
+    let uri = buffer.uri.clone().ok_or_else(invalid)?;
+
+    let start = view.byte_offset.map_or(0, |offset| offset.0) as usize
+        + accessor.byte_offset.map_or(0, |offset| offset.0) as usize;
+    let stride = view.byte_stride.map_or(64, |stride| stride.0);
+    let offsets = (0..accessor.count.0 as usize)
+        .map(|i| start + i * stride)
+        .collect();
+    Ok(Some((uri, offsets)))
+}
+
+fn read_matrices(
+    root: &Root,
+    bins: &[(String, Vec<u8>)],
+    skin: usize,
+) -> Result<Option<Vec<Matrix4<f32>>>, ScalingError> {
+    let Some((uri, offsets)) = matrix_layout(root, skin)? else {
+        return Ok(None);
+    };
+    let bin = bins
+        .iter()
+        .find_map(|(name, bin)| (*name == uri).then_some(bin))
+        .ok_or(ScalingError::BufferNotLoaded(uri))?;
+    offsets
+        .into_iter()
+        .map(|offset| {
+            let bytes = bin
+                .get(offset..offset + 64)
+                .ok_or(ScalingError::InverseBindMatricesInvalid(skin))?;
+            let values: Vec<_> = bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            Ok(Matrix4::from_column_slice(&values))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn write_matrices(
+    root: &Root,
+    bins: &mut [(String, Vec<u8>)],
+    skin: usize,
+    matrices: &[Matrix4<f32>],
+) -> Result<(), ScalingError> {
+    let Some((uri, offsets)) = matrix_layout(root, skin)? else {
+        return Ok(());
+    };
+    let bin = bins
+        .iter_mut()
+        .find_map(|(name, bin)| (*name == uri).then_some(bin))
+        .ok_or(ScalingError::BufferNotLoaded(uri))?;
+    for (offset, matrix) in offsets.into_iter().zip(matrices) {
+        let bytes = matrix
+            .as_slice()
+            .iter()
+            .flat_map(|value| value.to_le_bytes());
+        bin[offset..offset + 64]
+            .iter_mut()
+            .zip(bytes)
+            .for_each(|(byte, value)| *byte = value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measurements_parse() {
+        let source = "height 1.82\n# thigh\nLegUpL LegLowL 0.45\n";
+        let measurements = BodyMeasurements::parse(source).unwrap();
+        assert_eq!(measurements.height, Some(1.82));
+        assert_eq!(
+            measurements.segment("LegUpL", "LegLowL").unwrap().length,
+            0.45
+        );
+        assert_eq!(measurements.to_string(), source.replace("# thigh\n", ""));
+
+        assert!(BodyMeasurements::parse("height").is_err());
+        assert!(BodyMeasurements::parse("height 1.8\nheight 1.7").is_err());
+        assert!(BodyMeasurements::parse("LegUpL LegLowL -0.4").is_err());
+        assert!(BodyMeasurements::parse("LegUpL LegLowL").is_err());
+        assert!(BodyMeasurements::parse("LegUpL LegLowL 0.4 cm").is_err());
+        assert!(BodyMeasurements::parse("A B 0.4\nA B 0.5").is_err());
+    }
+
+    /// Leg standing on the origin, 1.6 tall with its mesh
+    const LEG: &str = r#"{
+        "asset": { "version": "2.0" },
+        "nodes": [
+            { "name": "Hip", "translation": [0.0, 1.0, 0.0], "children": [1] },
+            { "name": "Knee", "translation": [0.0, -0.5, 0.0], "children": [2] },
+            { "name": "Foot", "translation": [0.0, -0.5, 0.0] },
+            { "name": "Body", "mesh": 0, "skin": 0 }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 1 } }] }],
+        "skins": [{ "joints": [0, 1, 2], "inverseBindMatrices": 0 }],
+        "accessors": [
+            { "bufferView": 0, "count": 3, "componentType": 5126, "type": "MAT4" },
+            { "count": 1, "componentType": 5126, "type": "VEC3",
+              "min": [-0.1, 0.0, -0.1], "max": [0.1, 1.6, 0.1] }
+        ],
+        "bufferViews": [{ "buffer": 0, "byteLength": 192 }],
+        "buffers": [{ "uri": "Leg.bin", "byteLength": 192 }]
+    }"#;
+
+    #[test]
+    fn skeleton_scaling() {
+        let mut root = Root::from_str(LEG).unwrap();
+        let binds = [1.0, 0.5, 0.0]
+            .map(|y| nalgebra::Matrix4::new_translation(&Vector3::new(0.0, -y, 0.0)));
+        let bin = binds
+            .iter()
+            .flat_map(|ibm| ibm.as_slice().to_vec())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        let mut bins = vec![("Leg.bin".to_string(), bin)];
+
+        let measurements = BodyMeasurements::parse("height 1.76\nKnee Foot 0.6").unwrap();
+        scale(&mut root, &mut bins, &Rig::FirstSkin, &measurements).unwrap();
+        let translation = |index: usize| root.nodes[index].translation.unwrap()[1];
+        assert!((translation(0) - 1.1).abs() < 1e-5);
+        assert!((translation(1) + 0.55).abs() < 1e-5);
+        assert!((translation(2) + 0.6).abs() < 1e-5);
+
+        let ibm = |index: usize| {
+            let values: Vec<_> = bins[0].1[index * 64..(index + 1) * 64]
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            nalgebra::Matrix4::from_column_slice(&values)
+        };
+        // a vertex halfway down the shin stays halfway between the scaled knee and foot
+        let knee = nalgebra::Matrix4::new_translation(&Vector3::new(0.0, 1.1 - 0.55, 0.0));
+        let skinned = knee * ibm(1) * nalgebra::Vector4::new(0.05, 0.25, 0.0, 1.0);
+        assert!((skinned.y - 0.25).abs() < 1e-5);
+        // across the bone only the height scales
+        assert!((skinned.x - 0.055).abs() < 1e-5);
+        // the hip only scales with the height
+        let hip = nalgebra::Matrix4::new_translation(&Vector3::new(0.0, 1.1, 0.0));
+        let skinned = hip * ibm(0) * nalgebra::Vector4::new(0.0, 1.6, 0.0, 1.0);
+        assert!((skinned.y - 1.76).abs() < 1e-5);
+
+        let mut root = Root::from_str(LEG).unwrap();
+        let reversed = BodyMeasurements::parse("Foot Knee 0.6").unwrap();
+        let result = scale(&mut root, &mut bins, &Rig::FirstSkin, &reversed);
+        assert!(matches!(result, Err(ScalingError::NotDescendant { .. })));
+    }
+
+    #[test]
+    fn rotated_skeleton_height() {
+        // leg along Z without mesh, under an armature turning it Y up
+        let mut root = Root::from_str(
+            r#"{
+                "asset": { "version": "2.0" },
+                "nodes": [
+                    { "name": "Armature", "rotation": [-0.7071068, 0.0, 0.0, 0.7071068], "children": [1] },
+                    { "name": "Hip", "translation": [0.0, 0.0, 1.0], "children": [2] },
+                    { "name": "Knee", "translation": [0.0, 0.0, -0.5], "children": [3] },
+                    { "name": "Foot", "translation": [0.0, 0.0, -0.5] }
+                ],
+                "skins": [{ "joints": [1, 2, 3] }]
+            }"#,
+        )
+        .unwrap();
+        let measurements = BodyMeasurements::parse("height 1.8").unwrap();
+        scale(&mut root, &mut [], &Rig::FirstSkin, &measurements).unwrap();
+        let translation = |index: usize| root.nodes[index].translation.unwrap()[2];
+        assert!((translation(1) - 1.8).abs() < 1e-5);
+        assert!((translation(2) + 0.9).abs() < 1e-5);
+        assert!((translation(3) + 0.9).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{f32::consts::TAU, fmt::Display};
+
+use nalgebra::UnitQuaternion;
+
+use crate::to_gltf::Joint;
+
+/// Smoothing of the joint rotations, with optional filters for single joints
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Smoothing {
+    /// Filter of the joints that are not listed
+    pub filter: Filter,
+    pub joints: Vec<(Joint, Filter)>,
+}
+impl Smoothing {
+    pub fn filter(&self, joint: &str) -> Filter {
+        self.joints
+            .iter()
+            .find_map(|(j, filter)| (j == joint).then_some(*filter))
+            .unwrap_or(self.filter)
+    }
+
+    pub fn set(&mut self, joint: Joint, filter: Filter) {
+        match self.joints.iter_mut().find(|(j, _)| *j == joint) {
+            Some((_, old)) => *old = filter,
+            None => self.joints.push((joint, filter)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Filter {
+    #[default]
+    Off,
+    /// SLERP low-pass run forwards and backwards, so that it doesn't lag
+    LowPass {
+        /// Cutoff frequency in Hz
+        cutoff: f32,
+    },
+    /// One-euro filter, smooths slow movement and follows fast movement
+    OneEuro {
+        /// Cutoff frequency in Hz when still
+        min_cutoff: f32,
+        /// Increase of the cutoff per rad/s of angular speed
+        beta: f32,
+    },
+}
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::Off,
+        Filter::LowPass { cutoff: 6.0 },
+        Filter::LowPass { cutoff: 12.0 },
+        Filter::OneEuro {
+            min_cutoff: 1.0,
+            beta: 0.5,
+        },
+        Filter::OneEuro {
+            min_cutoff: 2.0,
+            beta: 2.0,
+        },
+    ];
+
+    /// Smooths rotations sampled at `times` in seconds
+    pub fn apply(&self, times: &[f32], rotations: &mut [UnitQuaternion<f32>]) {
+        let len = times.len().min(rotations.len());
+        let (times, rotations) = (&times[..len], &mut rotations[..len]);
+        match *self {
+            Filter::Off => {}
+            Filter::LowPass { cutoff } => {
+                low_pass(times.iter().copied(), rotations.iter_mut(), cutoff);
+                low_pass(
+                    times.iter().rev().copied(),
+                    rotations.iter_mut().rev(),
+                    cutoff,
+                );
+            }
+            Filter::OneEuro { min_cutoff, beta } => {
+                one_euro(times, rotations, min_cutoff, beta);
+            }
+        }
+    }
+}
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::Off => write!(f, "No smoothing"),
+            Filter::LowPass { cutoff } => write!(f, "Low-pass {cutoff} Hz"),
+            Filter::OneEuro { min_cutoff, beta } => {
+                write!(f, "One-euro {min_cutoff} Hz, beta {beta}")
+            }
+        }
+    }
+}
+
+/// Weight of the new sample in an exponential filter
+fn smoothing_factor(dt: f32, cutoff: f32) -> f32 {
+    let r = TAU * cutoff * dt.abs();
+    r / (r + 1.0)
+}
+
+fn low_pass<'a>(
+    times: impl Iterator<Item = f32>,
+    rotations: impl Iterator<Item = &'a mut UnitQuaternion<f32>>,
+    cutoff: f32,
+) {
+    let mut previous: Option<(f32, UnitQuaternion<f32>)> = None;
+    for (t, q) in times.zip(rotations) {
+        if let Some((t_prev, q_prev)) = previous {
+            let alpha = smoothing_factor(t - t_prev, cutoff);
+            *q = q_prev.try_slerp(q, alpha, f32::EPSILON).unwrap_or(*q);
+        }
+        previous = Some((t, *q));
+    }
+}
+
+fn one_euro(times: &[f32], rotations: &mut [UnitQuaternion<f32>], min_cutoff: f32, beta: f32) {
+    // cutoff of the angular speed estimate, as suggested by the authors
+    const SPEED_CUTOFF: f32 = 1.0;
+
+    let mut speed = 0.0;
+    for i in 1..rotations.len() {
+        let dt = times[i] - times[i - 1];
+        if dt <= 0.0 {
+            rotations[i] = rotations[i - 1];
+            continue;
+        }
+        let (q_prev, q) = (rotations[i - 1], rotations[i]);
+
+        let raw_speed = q_prev.angle_to(&q) / dt;
+        speed += smoothing_factor(dt, SPEED_CUTOFF) * (raw_speed - speed);
+        let alpha = smoothing_factor(dt, min_cutoff + beta * speed);
+        rotations[i] = q_prev.try_slerp(&q, alpha, f32::EPSILON).unwrap_or(q);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gltf::json::Index;
+
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        fixtures::{noisy_track, recording, rig},
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, sample_motion},
+        tracks,
+    };
+
+    #[test]
+    fn smoothing_filters() {
+        let (times, rotations) = noisy_track();
+        let truth = |t: f32| UnitQuaternion::from_euler_angles(0.0, 0.0, t.min(0.5));
+        let error = |rotations: &[UnitQuaternion<f32>]| {
+            // still part of the track, after the filters settle
+            (100..180)
+                .map(|i| rotations[i].angle_to(&truth(times[i])))
+                .fold(0.0, f32::max)
+        };
+
+        for filter in Filter::ALL.into_iter().skip(1) {
+            let mut smoothed = rotations.clone();
+            tracks::align_hemispheres(&mut smoothed);
+            filter.apply(&times, &mut smoothed);
+            assert!(error(&smoothed) < error(&rotations) / 2.0, "{filter}");
+        }
+    }
+
+    #[test]
+    fn smoothing_per_joint() {
+        let mut smoothing = Smoothing {
+            filter: Filter::LowPass { cutoff: 6.0 },
+            ..Default::default()
+        };
+        smoothing.set("Lower".into(), Filter::Off);
+        assert_eq!(smoothing.filter("Lower"), Filter::Off);
+        assert_eq!(smoothing.filter("Upper"), Filter::LowPass { cutoff: 6.0 });
+
+        // both sensors shake around x
+        let mut model = recording();
+        for (_, stream) in &mut model {
+            for (i, record) in stream.iter_mut().enumerate() {
+                record.sample.gyr[0] += if i % 2 == 0 { 2.0 } else { -2.0 };
+            }
+        }
+        let motion = |smoothing| {
+            let options = ConvertOptions {
+                smoothing,
+                ..Default::default()
+            };
+            sample_motion(
+                &model,
+                &rig(),
+                &Calibration::default(),
+                &Mapping::default(),
+                &options,
+                &mut Vec::new(),
+            )
+            .unwrap()
+            .rotations
+        };
+        let jitter = |track: &[UnitQuaternion<f32>]| -> f32 {
+            track
+                .windows(2)
+                .map(|pair| pair[0].angle_to(&pair[1]))
+                .sum()
+        };
+
+        let raw = motion(Smoothing::default());
+        let smoothed = motion(smoothing);
+        let (upper, lower) = (Index::new(0), Index::new(1));
+        assert!(jitter(&smoothed[&upper]) < jitter(&raw[&upper]) / 2.0);
+        assert_eq!(smoothed[&lower], raw[&lower]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt::Write;
+
+use amcx_core::Model;
+use gltf::json::{Index, Node, Root};
+use nalgebra::UnitQuaternion;
+
+use crate::{
+    calibration::Calibration,
+    euler::EulerOrder,
+    mapping::Mapping,
+    to_gltf::{
+        ConvertOptions, ConvertingError, ConvertingWarning, Motion, NodeTree, get_node,
+        sample_motion,
+    },
+    tracks::FrameRate,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct BvhOptions {
+    /// Order of the rotation channels of every joint
+    pub order: EulerOrder,
+}
+
+/// Writes the motion as BVH, with the hierarchy taken from the rig of the target model
+pub fn convert(
+    gltf_model: &Root,
+    amcx_model: &Model,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    bvh_options: &BvhOptions,
+) -> Result<(String, Vec<ConvertingWarning>), ConvertingError> {
+    let mut warnings = Vec::new();
+    let Motion {
+        times,
+        rotations,
+        translation,
+    } = sample_motion(
+        amcx_model,
+        gltf_model,
+        calibration,
+        mapping,
+        options,
+        &mut warnings,
+    )?;
+
+    let joints = options.rig.joints(gltf_model)?;
+    let tree = NodeTree::new(gltf_model, &joints)?;
+    let [tree] = tree.as_slice() else {
+        return Err(ConvertingError::MultipleRoots(tree.len()));
+    };
+
+    let frame_count = times.len();
+    let frame_time = match options.frame_rate {
+        FrameRate::Uniform(fps) => 1.0 / fps as f32,
+        FrameRate::Recorded => {
+            let (first, last) = (times.first(), times.last());
+            let duration = last.zip(first).map_or(0.0, |(last, first)| last - first);
+            duration / frame_count.saturating_sub(1).max(1) as f32
+        }
+    };
+
+    let rotation_channels = bvh_options
+        .order
+        .axes()
+        .map(|axis| ["Xrotation", "Yrotation", "Zrotation"][axis])
+        .join(" ");
+
+    let mut bvh = String::from("HIERARCHY\n");
+    let mut channel_order = Vec::new();
+    write_joint(
+        &mut bvh,
+        gltf_model,
+        tree,
+        0,
+        &rotation_channels,
+        &mut channel_order,
+    )?;
+
+    let rest_position = get_node(gltf_model, tree.index)?
+        .translation
+        .unwrap_or_default();
+    let root_positions = match translation {
+        Some((index, translations)) if index == tree.index => translations,
+        _ => vec![rest_position.into(); frame_count],
+    };
+    let identity = vec![UnitQuaternion::identity(); frame_count];
+    let tracks: Vec<_> = channel_order
+        .iter()
+        .map(|index| rotations.get(index).unwrap_or(&identity))
+        .collect();
+
+    writeln!(bvh, "MOTION").unwrap();
+    writeln!(bvh, "Frames: {frame_count}").unwrap();
+    writeln!(bvh, "Frame Time: {frame_time:.6}").unwrap();
+    for frame in 0..frame_count {
+        let [x, y, z] = root_positions
+            .get(frame)
+            .map_or(rest_position, |p| (*p).into());
+        write!(bvh, "{x:.6} {y:.6} {z:.6}").unwrap();
+        for track in &tracks {
+            let q = track.get(frame).copied().unwrap_or_default();
+            for angle in bvh_options.order.decompose(&q) {
+                write!(bvh, " {:.4}", angle.to_degrees()).unwrap();
+            }
+        }
+        bvh.push('\n');
+    }
+
+    Ok((bvh, warnings))
+}
+
+fn write_joint(
+    bvh: &mut String,
+    root: &Root,
+    tree: &NodeTree,
+    depth: usize,
+    rotation_channels: &str,
+    channel_order: &mut Vec<Index<Node>>,
+) -> Result<(), ConvertingError> {
+    let indent = "\t".repeat(depth);
+    let node = get_node(root, tree.index)?;
+    let name = joint_name(node, tree.index);
+    channel_order.push(tree.index);
+
+    if depth == 0 {
+        writeln!(bvh, "ROOT {name}").unwrap();
+        writeln!(bvh, "{{").unwrap();
+        // the root is placed by its position channels
+        writeln!(bvh, "\tOFFSET 0.000000 0.000000 0.000000").unwrap();
+        writeln!(
+            bvh,
+            "\tCHANNELS 6 Xposition Yposition Zposition {rotation_channels}"
+        )
+        .unwrap();
+    } else {
+        let [x, y, z] = node.translation.unwrap_or_default();
+        writeln!(bvh, "{indent}JOINT {name}").unwrap();
+        writeln!(bvh, "{indent}{{").unwrap();
+        writeln!(bvh, "{indent}\tOFFSET {x:.6} {y:.6} {z:.6}").unwrap();
+        writeln!(bvh, "{indent}\tCHANNELS 3 {rotation_channels}").unwrap();
+    }
+
+    if tree.children.is_empty() {
+        writeln!(bvh, "{indent}\tEnd Site").unwrap();
+        writeln!(bvh, "{indent}\t{{").unwrap();
+        writeln!(bvh, "{indent}\t\tOFFSET 0.000000 0.000000 0.000000").unwrap();
+        writeln!(bvh, "{indent}\t}}").unwrap();
+    }
+    for child in &tree.children {
+        write_joint(
+            bvh,
+            root,
+            child,
+            depth + 1,
+            rotation_channels,
+            channel_order,
+        )?;
+    }
+    writeln!(bvh, "{indent}}}").unwrap();
+    Ok(())
+}
+
+/// BVH names can't contain whitespace, unnamed nodes are named by their index
+fn joint_name(node: &Node, index: Index<Node>) -> String {
+    match &node.name {
+        Some(name) => name.split_whitespace().collect::<Vec<_>>().join("_"),
+        None => format!("Node{}", index.value()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{recording, rig};
+
+    #[test]
+    fn export_bvh() {
+        let bvh_options = BvhOptions {
+            order: EulerOrder::Yxz,
+        };
+        let (bvh, _) = convert(
+            &rig(),
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &ConvertOptions::default(),
+            &bvh_options,
+        )
+        .unwrap();
+
+        let (hierarchy, motion) = bvh.split_once("MOTION\n").unwrap();
+        assert!(hierarchy.starts_with("HIERARCHY\nROOT Upper\n"));
+        assert!(hierarchy.contains("JOINT Lower"));
+        assert!(!hierarchy.contains("Prop"));
+        assert!(hierarchy.contains("CHANNELS 3 Yrotation Xrotation Zrotation"));
+        assert_eq!(
+            hierarchy.matches('{').count(),
+            hierarchy.matches('}').count()
+        );
+
+        let mut lines = motion.lines();
+        assert_eq!(lines.next(), Some("Frames: 100"));
+        assert_eq!(lines.next(), Some("Frame Time: 0.010000"));
+        let frames: Vec<_> = lines.collect();
+        assert_eq!(frames.len(), 100);
+        assert!(frames.iter().all(|frame| frame.split(' ').count() == 6 + 3));
+    }
+
+    #[test]
+    fn export_bvh_multiple_roots() {
+        let mut root = rig();
+        root.skins[0].joints = vec![gltf::json::Index::new(1), gltf::json::Index::new(2)];
+        let result = convert(
+            &root,
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &ConvertOptions {
+                skip_uncoupled: true,
+                ..Default::default()
+            },
+            &BvhOptions::default(),
+        );
+        assert!(matches!(result, Err(ConvertingError::MultipleRoots(2))));
+    }
+}
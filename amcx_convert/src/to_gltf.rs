@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
 
 use ahrs::{Ahrs, AhrsError};
 use amcx_core::{Model, Record, Sample};
@@ -14,35 +17,253 @@ use gltf::{
         validation::Checked,
     },
 };
-use nalgebra::{Matrix3, Rotation, Rotation3, Unit, UnitQuaternion, Vector3};
+use nalgebra::{Rotation, Unit, UnitQuaternion, Vector3};
 use thiserror::Error;
 
+use crate::{
+    calibration::{Calibration, Calibrator, HingeAxes, HingeCalibration},
+    chains::{self, ChainRule},
+    heading::{self, HeadingCorrection},
+    limits::JointLimits,
+    mapping::Mapping,
+    provenance::Provenance,
+    retarget::Retarget,
+    root_motion::{self, RootMotion},
+    smoothing::Smoothing,
+    tracks::{self, FrameRate, KeyInterpolation, KeyReduction, ReductionReport, Track},
+    world_frame::WorldFrame,
+};
+
 #[derive(Debug, Error)]
 pub enum ConvertingError {
     #[error("Sensor {0} is not coupled with any joint")]
     SensorNotCoupled(String),
-    #[error("AhrsError")]
-    AhrsError(AhrsError),
-    #[error("Unrecoverable sensor data")]
-    UnrecoverableSensorData,
+    #[error("Sensors {0} and {1} are coupled with the same joint")]
+    SensorsShareJoint(String, String),
+    #[error("Sensor {0} is missing from the hinge recording")]
+    HingeSensorMissing(String),
+    #[error("Not enough movement to estimate the hinge axis between {0} and {1}")]
+    HingeAxisUndetermined(String, String),
+    #[error("Recording has no sensors")]
+    EmptyRecording,
+    #[error("Sensor {0} has no samples")]
+    NoSamples(String),
+    #[error("Sensor {sensor} has {found} samples, expected {expected}")]
+    SampleCountMismatch {
+        sensor: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Target model has no skin")]
+    NoSkin,
+    #[error("Target model has no skin named {0}")]
+    SkinNotFound(String),
+    #[error("Target model has no node named {0}")]
+    RootNodeNotFound(String),
+    #[error("Node {0} referenced by the skin does not exist")]
+    NodeMissing(usize),
+    #[error("Rig has {0} root joints, but a single root is required")]
+    MultipleRoots(usize),
+    #[error("Rig has no root joint")]
+    NoRoot,
+    #[error("Calibration of sensor {0} has no samples in the stationary period")]
+    CalibrationNotStationary(String),
+    #[error("Calibration of sensor {0} has no rotation to take the axis from")]
+    CalibrationStatic(String),
+    #[error("Calibration of sensor {0} rotates around the gravity axis")]
+    CalibrationDegenerate(String),
+    #[error("AHRS failed on sensor {0}: {1:?}")]
+    AhrsError(String, AhrsError),
+    #[error("Unrecoverable sensor data of {0}: no valid accelerometer sample to fall back to")]
+    UnrecoverableSensorData(String),
+    #[error("Sensor {0} used for the root motion is missing from the recording")]
+    RootMotionSensorMissing(String),
+    #[error("Rig has no joint named {0}")]
+    JointNotFound(String),
+    #[error("Chain ending at {joint} has {count} unmeasured joints, which its weights don't fit")]
+    ChainWeightsMismatch { joint: String, count: usize },
+    #[error("Chain ending at {0} has no sensor on its end joint to follow")]
+    ChainEndUnmeasured(String),
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum ConvertingWarning {
+    #[error("Sensor {0} is not coupled with any joint and was skipped")]
+    SensorSkipped(String),
+    #[error("Calibration of sensor {0} is not coupled with any joint and was skipped")]
+    CalibrationSkipped(String),
+    #[error("Joint {joint} exceeded its limits in {hits} of {samples} samples")]
+    JointLimited {
+        joint: String,
+        hits: usize,
+        samples: usize,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Skip sensors and calibration entries without a joint instead of failing
+    pub skip_uncoupled: bool,
+    /// Part of the target model driven by the recording
+    pub rig: Rig,
+    pub frame_rate: FrameRate,
+    pub reduction: KeyReduction,
+    pub interpolation: KeyInterpolation,
+    pub smoothing: Smoothing,
+    /// Estimates the translation of the root joint if set
+    pub root_motion: Option<RootMotion>,
+    /// Joints without a limit are not constrained
+    pub limits: JointLimits,
+    /// Unmeasured joints outside of these chains keep their rest orientation
+    pub chains: Vec<ChainRule>,
+    /// Corrects the heading drift of the sensors if set
+    pub heading: Option<HeadingCorrection>,
+    /// Written into the extras of the asset and the animations if set
+    pub provenance: Option<Provenance>,
+    /// Converts for another skeleton and carries the motion over to the target model if set
+    pub retarget: Option<Retarget>,
+    /// Axis convention of the target model's world
+    pub world: WorldFrame,
+}
+
+/// Selects the joints of the target model
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Rig {
+    /// Joints of the first skin
+    #[default]
+    FirstSkin,
+    /// Joints of the skin with the given name
+    Skin(String),
+    /// Node with the given name and every node under it, skinned or not
+    Node(String),
+}
+impl Rig {
+    /// Every rig the target model can provide: skins by name, then named nodes
+    pub fn all(root: &Root) -> Vec<Rig> {
+        let skins = root
+            .skins
+            .iter()
+            .filter_map(|skin| skin.name.clone().map(Rig::Skin));
+        let nodes = root
+            .nodes
+            .iter()
+            .filter_map(|node| node.name.clone().map(Rig::Node));
+        [Rig::FirstSkin]
+            .into_iter()
+            .chain(skins)
+            .chain(nodes)
+            .collect()
+    }
+
+    pub fn joints(&self, root: &Root) -> Result<Vec<Index<Node>>, ConvertingError> {
+        match self {
+            Rig::FirstSkin => {
+                let skin = root.skins.first().ok_or(ConvertingError::NoSkin)?;
+                Ok(skin.joints.clone())
+            }
+            Rig::Skin(name) => {
+                let skin = root
+                    .skins
+                    .iter()
+                    .find(|skin| skin.name.as_deref() == Some(name))
+                    .ok_or_else(|| ConvertingError::SkinNotFound(name.clone()))?;
+                Ok(skin.joints.clone())
+            }
+            Rig::Node(name) => {
+                let index = root
+                    .nodes
+                    .iter()
+                    .position(|node| node.name.as_deref() == Some(name))
+                    .ok_or_else(|| ConvertingError::RootNodeNotFound(name.clone()))?;
+
+                let mut joints = Vec::new();
+                let mut queue = vec![Index::new(index as u32)];
+                while let Some(index) = queue.pop() {
+                    // guards against cyclic hierarchies in malformed files
+                    if joints.contains(&index) {
+                        continue;
+                    }
+                    let node = get_node(root, index)?;
+                    queue.extend(node.children.iter().flatten().rev());
+                    joints.push(index);
+                }
+                Ok(joints)
+            }
+        }
+    }
+}
+impl Display for Rig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rig::FirstSkin => write!(f, "First skin"),
+            Rig::Skin(name) => write!(f, "Skin {name}"),
+            Rig::Node(name) => write!(f, "Node {name}"),
+        }
+    }
 }
 
 pub type Joint = String;
 
+/// Converted model with the animation appended
+#[derive(Debug)]
+pub struct Conversion {
+    pub root: Root,
+    /// Content of the buffer added for the animation
+    pub bin: Vec<u8>,
+    pub warnings: Vec<ConvertingWarning>,
+    /// Present if keyframe reduction was enabled
+    pub reduction: Option<ReductionReport>,
+}
+
+/// Recording converted into its own animation
+#[derive(Debug, Clone, Copy)]
+pub struct Take<'a> {
+    /// Name of the animation, like the file name of the recording
+    pub name: &'a str,
+    pub model: &'a Model,
+}
+
 pub fn convert(
-    mut gltf_model: Root,
+    gltf_model: Root,
     bin_name: &str,
     amcx_model: &Model,
-    calibration: Option<&Model>,
-) -> Result<(Root, Vec<u8>), ConvertingError> {
-    let mut bin = Vec::new();
-    let count = amcx_model.first().unwrap().1.len();
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+) -> Result<Conversion, ConvertingError> {
+    convert_animations(
+        gltf_model,
+        bin_name,
+        [(None, amcx_model)],
+        calibration,
+        mapping,
+        options,
+    )
+}
 
-    let mut timestamps = amcx_model[0]
-        .1
-        .iter()
-        .map(|record| record.timestamp.as_secs_f32());
+/// Converts every take into a named animation, all of them stored in one buffer
+pub fn convert_takes(
+    gltf_model: Root,
+    bin_name: &str,
+    takes: &[Take],
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+) -> Result<Conversion, ConvertingError> {
+    let takes = takes.iter().map(|take| (Some(take.name), take.model));
+    convert_animations(gltf_model, bin_name, takes, calibration, mapping, options)
+}
 
+fn convert_animations<'a>(
+    mut gltf_model: Root,
+    bin_name: &str,
+    takes: impl IntoIterator<Item = (Option<&'a str>, &'a Model)>,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+) -> Result<Conversion, ConvertingError> {
+    let mut bin = Vec::new();
+    let mut warnings = Vec::new();
     let buffer = gltf_model.push(Buffer {
         byte_length: 0u64.into(), // calculated later
         name: None,
@@ -51,66 +272,224 @@ pub fn convert(
         extras: Default::default(),
     });
 
-    for timestamp in timestamps.clone() {
-        bin.write_f32::<LittleEndian>(timestamp).unwrap();
+    let mut reduction: Option<ReductionReport> = None;
+    for (take, (name, amcx_model)) in takes.into_iter().enumerate() {
+        let animations = gltf_model.animations.len();
+        let report = append_animation(
+            &mut gltf_model,
+            &mut bin,
+            buffer,
+            name,
+            amcx_model,
+            calibration,
+            mapping,
+            options,
+            &mut warnings,
+        )?;
+        // a take without any motion adds no animation
+        if let (Some(provenance), Some(animation)) = (
+            &options.provenance,
+            gltf_model.animations.get_mut(animations),
+        ) {
+            animation.extras = provenance.animation_extras(take, calibration, mapping, options);
+        }
+        if let Some(report) = report {
+            let total = reduction.get_or_insert_with(Default::default);
+            total.keys_removed += report.keys_removed;
+            total.tracks_skipped += report.tracks_skipped;
+            total.bytes_saved += report.bytes_saved;
+        }
     }
-    let min_time = timestamps.next().unwrap();
-    let max_time = timestamps.last().unwrap();
 
-    let view = View {
-        name: Some("timestamps".into()),
-        buffer,
-        byte_length: bin.len().into(),
-        byte_offset: None,
-        target: None,
-        byte_stride: None,
-        extensions: None,
-        extras: Default::default(),
-    };
-    let accessor = Accessor {
-        buffer_view: Some(gltf_model.push(view)),
-        byte_offset: None,
-        count: count.into(),
-        component_type: Checked::Valid(GenericComponentType(ComponentType::F32)),
-        type_: Checked::Valid(gltf::json::accessor::Type::Scalar),
-        min: Some([min_time].into()),
-        max: Some([max_time].into()),
-        name: None,
-        normalized: false,
-        sparse: None,
-        extensions: None,
-        extras: Default::default(),
+    if bin.is_empty() {
+        // nothing refers to it, and a buffer needs at least one byte
+        gltf_model.buffers.pop();
+    } else if let Some(buffer) = gltf_model.buffers.get_mut(buffer.value()) {
+        buffer.byte_length = bin.len().into();
+    }
+    if let Some(provenance) = &options.provenance {
+        gltf_model.asset.extras = provenance.asset_extras(calibration, mapping, options);
+    }
+
+    Ok(Conversion {
+        root: gltf_model,
+        bin,
+        warnings,
+        reduction,
+    })
+}
+
+/// Samples the recording and appends its animation, with its own views into `buffer`
+#[allow(clippy::too_many_arguments)]
+fn append_animation(
+    gltf_model: &mut Root,
+    bin: &mut Vec<u8>,
+    buffer: Index<Buffer>,
+    name: Option<&str>,
+    amcx_model: &Model,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    warnings: &mut Vec<ConvertingWarning>,
+) -> Result<Option<ReductionReport>, ConvertingError> {
+    let Motion {
+        times: timestamps,
+        rotations,
+        translation,
+    } = sample_motion(
+        amcx_model,
+        gltf_model,
+        calibration,
+        mapping,
+        options,
+        warnings,
+    )?;
+    let count = timestamps.len();
+
+    let mut tracks: Vec<_> = rotations
+        .into_iter()
+        .filter(|(_, rotations)| !rotations.is_empty())
+        .map(|(index, rotations)| {
+            let times = timestamps.clone();
+            (index, Track { times, rotations })
+        })
+        .collect();
+    tracks.sort_by_key(|(index, _)| index.value());
+    // cubic splines store an in-tangent, the value and an out-tangent per key
+    let values_per_key = match options.interpolation {
+        KeyInterpolation::CubicSpline => 3,
+        KeyInterpolation::Step | KeyInterpolation::Linear => 1,
     };
-    let input = gltf_model.push(accessor);
+    let translation_size = translation
+        .as_ref()
+        .map_or(0, |_| count * values_per_key * 3);
+    let full_size =
+        (count + tracks.len() * count * values_per_key * 4 + translation_size) * size_of::<f32>();
 
-    let view_begin = bin.len();
-    let view = gltf_model.push(View {
-        name: Some("rotations".into()),
+    let mut reduction = None;
+    if let KeyReduction::Tolerance(degrees) = options.reduction {
+        let tolerance = degrees.to_radians();
+        let mut report = ReductionReport::default();
+        let mut reduced = Vec::with_capacity(tracks.len());
+        for (index, track) in tracks {
+            let rest = get_node(gltf_model, index)?
+                .rotation
+                .map_or(UnitQuaternion::identity(), |r| {
+                    UnitQuaternion::from_quaternion(r.0.into())
+                });
+            if tracks::is_constant(&track.rotations, &rest, tolerance) {
+                report.tracks_skipped += 1;
+                report.keys_removed += track.times.len();
+                continue;
+            }
+            let track = tracks::reduce(&track, tolerance, options.interpolation);
+            report.keys_removed += count - track.times.len();
+            reduced.push((index, track));
+        }
+        tracks = reduced;
+        reduction = Some(report);
+    }
+    if tracks.is_empty() && translation.is_none() {
+        // every track matches the rest pose, an animation needs at least one channel
+        if let Some(report) = reduction.as_mut() {
+            report.bytes_saved = full_size;
+        }
+        return Ok(reduction);
+    }
+
+    // views of several takes are told apart by the take name
+    let view_name = |kind: &str| match name {
+        Some(name) => format!("{name} {kind}"),
+        None => kind.into(),
+    };
+    let take_begin = bin.len();
+    // there is at least one track or translation, so the timestamps are never empty
+    let timestamps_view = gltf_model.push(View {
+        name: Some(view_name("timestamps")),
         buffer,
         byte_length: 0u64.into(), // calculated later
-        byte_offset: Some(view_begin.into()),
+        byte_offset: Some(take_begin.into()),
         target: None,
         byte_stride: None,
         extensions: None,
         extras: Default::default(),
     });
-
-    let rotations = calculate_rotations(amcx_model, &gltf_model, calibration)?;
-    let mut outputs = Vec::new();
-    for (index, stream) in rotations {
-        if stream.is_empty() {
-            continue;
+    let write_times = |gltf_model: &mut Root, bin: &mut Vec<u8>, times: &[f32]| {
+        let begin = bin.len() - take_begin;
+        for timestamp in times {
+            bin.write_f32::<LittleEndian>(*timestamp).unwrap();
         }
+        let min_time = times.first().copied().unwrap_or_default();
+        let max_time = times.last().copied().unwrap_or_default();
+        gltf_model.push(Accessor {
+            buffer_view: Some(timestamps_view),
+            byte_offset: Some(begin.into()),
+            count: times.len().into(),
+            component_type: Checked::Valid(GenericComponentType(ComponentType::F32)),
+            type_: Checked::Valid(gltf::json::accessor::Type::Scalar),
+            min: Some([min_time].into()),
+            max: Some([max_time].into()),
+            name: None,
+            normalized: false,
+            sparse: None,
+            extensions: None,
+            extras: Default::default(),
+        })
+    };
 
+    // tracks that kept every key share the timestamps of the recording
+    let mut shared_input = None;
+    let mut inputs = Vec::with_capacity(tracks.len());
+    for (_, track) in &tracks {
+        let input = match track.times.len() == count {
+            true => *shared_input.get_or_insert_with(|| write_times(gltf_model, bin, &timestamps)),
+            false => write_times(gltf_model, bin, &track.times),
+        };
+        inputs.push(input);
+    }
+    // the root translation is never reduced
+    let translation_input = translation
+        .as_ref()
+        .map(|_| *shared_input.get_or_insert_with(|| write_times(gltf_model, bin, &timestamps)));
+    if let Some(view) = gltf_model.buffer_views.get_mut(timestamps_view.value()) {
+        view.byte_length = (bin.len() - take_begin).into();
+    }
+
+    let view_begin = bin.len();
+    // a root translation alone does not need a view of rotations
+    let view = (!tracks.is_empty()).then(|| {
+        gltf_model.push(View {
+            name: Some(view_name("rotations")),
+            buffer,
+            byte_length: 0u64.into(), // calculated later
+            byte_offset: Some(view_begin.into()),
+            target: None,
+            byte_stride: None,
+            extensions: None,
+            extras: Default::default(),
+        })
+    });
+
+    let mut outputs = Vec::new();
+    for ((index, track), input) in tracks.into_iter().zip(inputs) {
         let begin = bin.len() - view_begin;
-        stream.into_iter().for_each(|q| {
+        let values = match options.interpolation {
+            KeyInterpolation::CubicSpline => tracks::cubic_spline(&track),
+            KeyInterpolation::Step | KeyInterpolation::Linear => track
+                .rotations
+                .into_iter()
+                .map(|q| q.into_inner())
+                .collect(),
+        };
+        let count = values.len();
+        values.into_iter().for_each(|q| {
             q.coords.iter().for_each(|c| {
                 bin.write_f32::<LittleEndian>(*c).unwrap();
             })
         });
 
         let accessor = Accessor {
-            buffer_view: Some(view),
+            buffer_view: view,
             byte_offset: Some(begin.into()),
             count: count.into(),
             component_type: Checked::Valid(GenericComponentType(ComponentType::F32)),
@@ -124,25 +503,80 @@ pub fn convert(
             extras: Default::default(),
         };
         let output = gltf_model.push(accessor);
-        outputs.push((index.clone(), output));
+        outputs.push((index, input, output));
     }
 
-    gltf_model.buffer_views.get_mut(view.value()).map(|view| {
-        view.byte_length = (bin.len() - view.byte_offset.unwrap().0 as usize).into();
-    });
-    gltf_model.buffers.get_mut(buffer.value()).map(|buffer| {
-        buffer.byte_length = bin.len().into();
-    });
+    if let Some(view) = view.and_then(|view| gltf_model.buffer_views.get_mut(view.value())) {
+        view.byte_length = (bin.len() - view_begin).into();
+    }
 
+    let mut translation_output = None;
+    if let Some(((node, translations), input)) = translation.zip(translation_input) {
+        let view_begin = bin.len();
+        let view = gltf_model.push(View {
+            name: Some(view_name("translations")),
+            buffer,
+            byte_length: 0u64.into(), // calculated later
+            byte_offset: Some(view_begin.into()),
+            target: None,
+            byte_stride: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+        let values = match options.interpolation {
+            KeyInterpolation::CubicSpline => {
+                tracks::cubic_spline_vectors(&timestamps, &translations)
+            }
+            KeyInterpolation::Step | KeyInterpolation::Linear => translations,
+        };
+        values.iter().for_each(|v| {
+            v.iter().for_each(|c| {
+                bin.write_f32::<LittleEndian>(*c).unwrap();
+            })
+        });
+        if let Some(view) = gltf_model.buffer_views.get_mut(view.value()) {
+            view.byte_length = (bin.len() - view_begin).into();
+        }
+
+        let accessor = Accessor {
+            buffer_view: Some(view),
+            byte_offset: Some(0u64.into()),
+            count: values.len().into(),
+            component_type: Checked::Valid(GenericComponentType(ComponentType::F32)),
+            type_: Checked::Valid(gltf::json::accessor::Type::Vec3),
+            min: None,
+            max: None,
+            name: None,
+            normalized: false,
+            sparse: None,
+            extensions: None,
+            extras: Default::default(),
+        };
+        translation_output = Some((node, input, gltf_model.push(accessor)));
+    }
+
+    if let Some(report) = reduction.as_mut() {
+        report.bytes_saved = full_size.saturating_sub(bin.len() - take_begin);
+    }
+
+    let interpolation = match options.interpolation {
+        KeyInterpolation::Step => Interpolation::Step,
+        KeyInterpolation::Linear => Interpolation::Linear,
+        KeyInterpolation::CubicSpline => Interpolation::CubicSpline,
+    };
     let mut samplers = Vec::new();
     let mut channels = Vec::new();
 
-    for (node, output) in outputs {
+    let outputs = outputs
+        .into_iter()
+        .map(|output| (output, Property::Rotation))
+        .chain(translation_output.map(|output| (output, Property::Translation)));
+    for ((node, input, output), path) in outputs {
         let sampler = Index::push(
             &mut samplers,
             Sampler {
                 input,
-                interpolation: Checked::Valid(Interpolation::Linear),
+                interpolation: Checked::Valid(interpolation),
                 output,
                 extensions: None,
                 extras: Default::default(),
@@ -152,7 +586,7 @@ pub fn convert(
             sampler,
             target: Target {
                 node,
-                path: Checked::Valid(Property::Rotation),
+                path: Checked::Valid(path),
                 extensions: None,
                 extras: Default::default(),
             },
@@ -164,53 +598,216 @@ pub fn convert(
     let animation = Animation {
         channels,
         samplers,
-        name: None,
+        name: name.map(Into::into),
         extensions: None,
         extras: Default::default(),
     };
     gltf_model.push(animation);
 
-    Ok((gltf_model, bin))
+    Ok(reduction)
 }
 
-fn calculate_rotations(
+/// Animation sampled at the keyframe times
+pub(crate) struct Motion {
+    /// Keyframe times in seconds
+    pub times: Vec<f32>,
+    /// Local rotation of every joint
+    pub rotations: HashMap<Index<Node>, Vec<UnitQuaternion<f32>>>,
+    /// Root joint and its translation, present if root motion is estimated
+    pub translation: Option<(Index<Node>, Vec<Vector3<f32>>)>,
+}
+
+pub(crate) fn sample_motion(
     model: &Model,
     root: &Root,
-    calibration: Option<&Model>,
-) -> Result<HashMap<Index<Node>, Vec<UnitQuaternion<f32>>>, ConvertingError> {
-    let skin = root.skins.iter().next().unwrap();
-    let get_joints: HashMap<&str, Index<Node>> = skin
-        .joints
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    warnings: &mut Vec<ConvertingWarning>,
+) -> Result<Motion, ConvertingError> {
+    let (sensor, stream) = model.first().ok_or(ConvertingError::EmptyRecording)?;
+    if stream.is_empty() {
+        return Err(ConvertingError::NoSamples(sensor.clone()));
+    }
+    let timestamps: Vec<_> = stream
         .iter()
-        .filter_map(|index| {
-            let name = root.get(*index).unwrap().name.as_ref();
-            name.map(|name| (name.as_str(), index.to_owned()))
-        })
+        .map(|record| record.timestamp.as_secs_f32())
         .collect();
 
-    let calibrators = Calibrator::new(calibration.unwrap_or(&Vec::new()))?;
-    let mut indexed_calibrators = HashMap::new();
-    for (sensor, calibrator) in calibrators {
-        let index = get_joints
-            .get(sensor.as_str())
-            .ok_or(ConvertingError::SensorNotCoupled(sensor.into()))?;
-        indexed_calibrators.insert(index.clone(), calibrator);
+    let Motion {
+        rotations,
+        translation,
+        ..
+    } = match &options.retarget {
+        Some(retarget) => {
+            let source_options = retarget.source_options(options);
+            let source_motion = skeleton_motion(
+                model,
+                &retarget.source,
+                calibration,
+                mapping,
+                &source_options,
+                &timestamps,
+                warnings,
+            )?;
+            retarget.apply(root, &options.rig, source_motion)?
+        }
+        None => skeleton_motion(
+            model,
+            root,
+            calibration,
+            mapping,
+            options,
+            &timestamps,
+            warnings,
+        )?,
+    };
+
+    match options.frame_rate {
+        FrameRate::Recorded => Ok(Motion {
+            times: timestamps,
+            rotations,
+            translation,
+        }),
+        FrameRate::Uniform(fps) => {
+            let start = timestamps.first().copied().unwrap_or_default();
+            let end = timestamps.last().copied().unwrap_or_default();
+            let times = tracks::uniform_times(start, end, fps);
+            let rotations = rotations
+                .into_iter()
+                .map(|(index, stream)| (index, tracks::resample(&timestamps, &stream, &times)))
+                .collect();
+            let translation = translation.map(|(index, translations)| {
+                (
+                    index,
+                    tracks::resample_vectors(&timestamps, &translations, &times),
+                )
+            });
+            Ok(Motion {
+                times,
+                rotations,
+                translation,
+            })
+        }
     }
+}
 
-    let mut joints_with_stream = HashMap::new();
-    for (sensor, stream) in model {
-        let index = get_joints
-            .get(sensor.as_str())
-            .ok_or(ConvertingError::SensorNotCoupled(sensor.into()))?;
-        joints_with_stream.insert(index.clone(), stream);
+/// Smoothed motion of the joints of `root` at every sample
+fn skeleton_motion(
+    model: &Model,
+    root: &Root,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    timestamps: &[f32],
+    warnings: &mut Vec<ConvertingWarning>,
+) -> Result<Motion, ConvertingError> {
+    let mut rotations = calculate_rotations(model, root, calibration, mapping, options, warnings)?;
+    let translation = match &options.root_motion {
+        Some(root_motion) => Some(root_translation(
+            model,
+            root,
+            mapping,
+            options,
+            root_motion,
+        )?),
+        None => None,
+    };
+
+    for (index, stream) in rotations.iter_mut() {
+        tracks::align_hemispheres(stream);
+        let joint = get_node(root, *index)?.name.as_deref().unwrap_or_default();
+        options.smoothing.filter(joint).apply(timestamps, stream);
     }
+    Ok(Motion {
+        times: timestamps.to_vec(),
+        rotations,
+        translation,
+    })
+}
 
-    let sample_count = model[0].1.len();
+/// Root joint of the tree driven by the root motion sensor and its translation at every sample
+///
+/// The estimated displacement is added to the rest translation of the root joint.
+fn root_translation(
+    model: &Model,
+    root: &Root,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    root_motion: &RootMotion,
+) -> Result<(Index<Node>, Vec<Vector3<f32>>), ConvertingError> {
+    let model = &mapping.apply(model);
+    let find_stream = |sensor: &str| {
+        model
+            .iter()
+            .find_map(|(s, stream)| (s == sensor).then_some(stream.as_slice()))
+            .ok_or_else(|| ConvertingError::RootMotionSensorMissing(sensor.into()))
+    };
+
+    let sensor = &root_motion.sensor;
+    let joints = options.rig.joints(root)?;
+    let joint = mapping
+        .joint(sensor)
+        .and_then(|joint| {
+            joints.iter().find(|index| {
+                root.get(**index).and_then(|node| node.name.as_deref()) == Some(joint)
+            })
+        })
+        .ok_or_else(|| ConvertingError::SensorNotCoupled(sensor.clone()))?;
+    let tree = NodeTree::new(root, &joints)?;
+    let root_joint = tree
+        .iter()
+        .find(|tree_root| tree_root.contains(*joint))
+        .map_or(*joint, |tree_root| tree_root.index);
+
+    let stream = find_stream(sensor)?;
+    let orientations = process_ahrs(sensor, stream)?;
+    let mut still = root_motion.stillness(stream);
+    if !root_motion.contact_sensors.is_empty() {
+        still = vec![false; stream.len()];
+        for contact in &root_motion.contact_sensors {
+            let contact_still = root_motion.stillness(find_stream(contact)?);
+            still
+                .iter_mut()
+                .zip(contact_still)
+                .for_each(|(still, contact_still)| *still |= contact_still);
+        }
+    }
+
+    let rest = Vector3::from(get_node(root, root_joint)?.translation.unwrap_or_default());
+    let translations = root_motion::integrate(stream, &orientations, &still)
+        .into_iter()
+        .map(|displacement| rest + options.world.displacement(&displacement))
+        .collect();
+    Ok((root_joint, translations))
+}
+
+pub(crate) fn calculate_rotations(
+    model: &Model,
+    root: &Root,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+    warnings: &mut Vec<ConvertingWarning>,
+) -> Result<HashMap<Index<Node>, Vec<UnitQuaternion<f32>>>, ConvertingError> {
+    let joints = options.rig.joints(root)?;
+    let mut get_joints: HashMap<&str, Index<Node>> = HashMap::new();
+    for index in &joints {
+        if let Some(name) = get_node(root, *index)?.name.as_ref() {
+            get_joints.insert(name.as_str(), *index);
+        }
+    }
+    let get_index = |sensor: &str| {
+        mapping
+            .joint(sensor)
+            .and_then(|joint| get_joints.get(joint))
+            .cloned()
+            .ok_or(ConvertingError::SensorNotCoupled(sensor.into()))
+    };
 
     let mut static_orientation = HashMap::new();
-    let mut joint_rotations = HashMap::new();
-    for index in skin.joints.iter().cloned() {
-        match root.get(index).unwrap().rotation {
+    for index in joints.iter().cloned() {
+        match get_node(root, index)?.rotation {
             Some(s) => {
                 static_orientation.insert(index, UnitQuaternion::from_quaternion(s.0.into()));
             }
@@ -218,35 +815,234 @@ fn calculate_rotations(
                 static_orientation.insert(index, UnitQuaternion::identity());
             }
         }
+    }
 
-        let mut rotations = match joints_with_stream.remove(&index) {
-            Some(stream) => process_ahrs(stream)?,
-            None => vec![UnitQuaternion::identity(); sample_count],
+    let tree = NodeTree::new(root, &joints)?;
+    for tree_root in &tree {
+        tree_root.for_each_bf(&mut |node| {
+            let s = static_orientation.get(&node.index).unwrap().clone();
+            for child in &node.children {
+                let child_orientation = static_orientation.get_mut(&child.index).unwrap();
+                *child_orientation = s * (*child_orientation);
+            }
+        });
+    }
+
+    let model = &mapping.apply(model);
+    let reference = calibration.reference.map(|r| mapping.apply(r));
+    let rest = |sensor: &str| match get_index(sensor) {
+        Ok(index) => options.world.rotation_to_ahrs(&static_orientation[&index]),
+        // dropped below
+        Err(_) => UnitQuaternion::identity(),
+    };
+    let calibrators = Calibrator::new(reference.as_ref(), calibration.method, rest)?;
+    let mut indexed_calibrators = HashMap::new();
+    for (sensor, calibrator) in calibrators {
+        match get_index(&sensor) {
+            Ok(index) => {
+                indexed_calibrators.insert(index, calibrator);
+            }
+            Err(_) if options.skip_uncoupled => {
+                warnings.push(ConvertingWarning::CalibrationSkipped(sensor));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let (_, first_stream) = model.first().ok_or(ConvertingError::EmptyRecording)?;
+    let sample_count = first_stream.len();
+
+    let mut joints_with_stream = HashMap::new();
+    for (sensor, stream) in model {
+        if stream.is_empty() {
+            return Err(ConvertingError::NoSamples(sensor.clone()));
+        }
+        if stream.len() != sample_count {
+            return Err(ConvertingError::SampleCountMismatch {
+                sensor: sensor.clone(),
+                expected: sample_count,
+                found: stream.len(),
+            });
+        }
+        match get_index(sensor) {
+            Ok(index) => {
+                if let Some((other, _)) = joints_with_stream.insert(index, (sensor, stream)) {
+                    return Err(ConvertingError::SensorsShareJoint(
+                        other.clone(),
+                        sensor.clone(),
+                    ));
+                }
+            }
+            Err(_) if options.skip_uncoupled => {
+                warnings.push(ConvertingWarning::SensorSkipped(sensor.clone()));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    for hinge in &calibration.hinges {
+        let recording = mapping.apply(hinge.recording);
+        let axes = HingeCalibration {
+            recording: &recording,
+            ..hinge.clone()
+        }
+        .estimate_axes()?;
+        let joint = get_index(&hinge.child)?;
+        let world_axis = static_orientation.get(&joint).unwrap() * hinge.axis;
+        let world_axis = options.world.axis_to_ahrs(&world_axis);
+        // the sign of the estimate is arbitrary, it follows the calibration of the child
+        let expected = match indexed_calibrators.get(&joint) {
+            Some(calibrator) => calibrator.sensor_axis(&world_axis),
+            None => world_axis,
+        };
+        let axes = match axes.child.dot(&expected) < 0.0 {
+            true => HingeAxes {
+                parent: -axes.parent,
+                child: -axes.child,
+            },
+            false => axes,
+        };
+        for (sensor, sensor_axis) in [(&hinge.parent, axes.parent), (&hinge.child, axes.child)] {
+            indexed_calibrators
+                .entry(get_index(sensor)?)
+                .or_default()
+                .align_axis(&sensor_axis, &world_axis);
+        }
+    }
+
+    let mut parents = HashMap::new();
+    let mut top_down = Vec::new();
+    for tree_root in &tree {
+        tree_root.for_each_bf(&mut |node| {
+            top_down.push(node.index);
+            for child in &node.children {
+                parents.insert(child.index, node.index);
+            }
+        });
+    }
+    let measured: HashSet<_> = joints_with_stream.keys().cloned().collect();
+    let measured_parent = |index: &Index<Node>| {
+        let mut parent = parents.get(index);
+        while let Some(index) = parent.filter(|index| !measured.contains(index)) {
+            parent = parents.get(index);
+        }
+        parent.cloned()
+    };
+
+    let mut sensor_rotations = HashMap::new();
+    let mut stillness = HashMap::new();
+    for (index, (sensor, stream)) in joints_with_stream {
+        let rotations = match &options.heading {
+            Some(heading) => {
+                // headings are referenced to the start of the calibration recording
+                let calibration_stream = reference
+                    .iter()
+                    .flatten()
+                    .find_map(|(s, stream)| (s == sensor).then_some(stream.as_slice()))
+                    .unwrap_or_default();
+                let stream = heading::continued(calibration_stream, stream);
+                let still = heading.stillness(&stream);
+                let mut rotations = match heading.gyro_bias {
+                    true => process_ahrs(sensor, &heading.remove_bias(&stream, &still))?,
+                    false => process_ahrs(sensor, &stream)?,
+                };
+                heading::align(&mut rotations);
+                rotations.drain(..calibration_stream.len());
+                stillness.insert(index, still[calibration_stream.len()..].to_vec());
+                rotations
+            }
+            None => process_ahrs(sensor, stream)?,
         };
+        sensor_rotations.insert(index, rotations);
+    }
+    if options
+        .heading
+        .as_ref()
+        .is_some_and(|heading| heading.chain)
+    {
+        // parents first, so that the corrections add up along the chain
+        for index in top_down.iter().filter(|index| measured.contains(index)) {
+            let Some(parent) = measured_parent(index) else {
+                continue;
+            };
+            let still: Vec<_> = stillness[&parent]
+                .iter()
+                .zip(&stillness[index])
+                .map(|(p, c)| *p && *c)
+                .collect();
+            let parent_rotations = sensor_rotations[&parent].clone();
+            let child_rotations = sensor_rotations.get_mut(index).unwrap();
+            heading::align_to_parent(&parent_rotations, child_rotations, &still);
+        }
+    }
+
+    let mut joint_rotations = HashMap::new();
+    for index in joints.iter().cloned() {
+        let s = *static_orientation.get(&index).unwrap();
+        let mut rotations = sensor_rotations
+            .remove(&index)
+            .unwrap_or_else(|| vec![UnitQuaternion::identity(); sample_count]);
         if let Some(calibrator) = indexed_calibrators.get(&index) {
             rotations
                 .iter_mut()
                 .for_each(|q| *q = calibrator.calibrate(*q));
         }
+        rotations.iter_mut().for_each(|q| {
+            *q = options.world.rotation(q) * s;
+        });
         joint_rotations.insert(index, rotations);
     }
 
-    let tree = NodeTree::new(root);
-    for tree_root in tree {
-        tree_root.for_each_bf(&mut |node| {
-            let s = static_orientation.get(&node.index).unwrap().clone();
-            for child in &node.children {
-                let child_orientation = static_orientation.get_mut(&child.index).unwrap();
-                *child_orientation = s * (*child_orientation);
+    for rule in &options.chains {
+        let end = *get_joints
+            .get(rule.end.as_str())
+            .ok_or_else(|| ConvertingError::JointNotFound(rule.end.clone()))?;
+        if !measured.contains(&end) {
+            return Err(ConvertingError::ChainEndUnmeasured(rule.end.clone()));
+        }
+        let start = measured_parent(&end);
+        let mut chain = Vec::new();
+        let mut parent = parents.get(&end).cloned();
+        while let Some(index) = parent.filter(|index| Some(*index) != start) {
+            chain.push(index);
+            parent = parents.get(&index).cloned();
+        }
+        chain.reverse();
+        let shares = rule.distribution.shares(chain.len()).ok_or_else(|| {
+            ConvertingError::ChainWeightsMismatch {
+                joint: rule.end.clone(),
+                count: chain.len(),
             }
+        })?;
+
+        // rotations of the measured joints without their rest orientation,
+        // a chain without a measured start begins at the rest pose
+        let without_rest = |index: &Index<Node>| {
+            let s_inv = static_orientation.get(index).unwrap().inverse();
             joint_rotations
-                .get_mut(&node.index)
+                .get(index)
                 .unwrap()
-                .iter_mut()
-                .for_each(|q| {
-                    *q = (*q) * s;
-                });
-        });
+                .iter()
+                .map(|q| q * s_inv)
+                .collect::<Vec<_>>()
+        };
+        let end_rotations = without_rest(&end);
+        let start_rotations = match start {
+            Some(start) => without_rest(&start),
+            None => vec![UnitQuaternion::identity(); sample_count],
+        };
+        for (index, share) in chain.into_iter().zip(shares) {
+            let s = static_orientation.get(&index).unwrap();
+            let rotations = start_rotations
+                .iter()
+                .zip(&end_rotations)
+                .map(|(start, end)| chains::partial(start, end, share) * s)
+                .collect();
+            joint_rotations.insert(index, rotations);
+        }
+    }
+
+    for tree_root in tree {
         tree_root.for_each_df(&mut |node| {
             let parent_rotations_inv: Vec<_> = joint_rotations
                 .get(&node.index)
@@ -268,10 +1064,46 @@ fn calculate_rotations(
         });
     }
 
+    for index in joints.iter().cloned() {
+        let node = get_node(root, index)?;
+        let Some((joint, limit)) = node
+            .name
+            .as_ref()
+            .and_then(|name| Some((name, options.limits.get(name)?)))
+        else {
+            continue;
+        };
+        // limits are relative to the rest pose
+        let rest = node.rotation.map_or(UnitQuaternion::identity(), |r| {
+            UnitQuaternion::from_quaternion(r.0.into())
+        });
+        let mut hits = 0;
+        for q in joint_rotations.get_mut(&index).unwrap() {
+            let (projected, hit) = limit.project(&(rest.inverse() * *q));
+            *q = rest * projected;
+            hits += hit as usize;
+        }
+        if hits > 0 {
+            warnings.push(ConvertingWarning::JointLimited {
+                joint: joint.clone(),
+                hits,
+                samples: sample_count,
+            });
+        }
+    }
+
     Ok(joint_rotations)
 }
 
-fn process_ahrs(stream: &[Record]) -> Result<Vec<UnitQuaternion<f32>>, ConvertingError> {
+pub(crate) fn get_node(root: &Root, index: Index<Node>) -> Result<&Node, ConvertingError> {
+    root.get(index)
+        .ok_or(ConvertingError::NodeMissing(index.value()))
+}
+
+pub(crate) fn process_ahrs(
+    sensor: &str,
+    stream: &[Record],
+) -> Result<Vec<UnitQuaternion<f32>>, ConvertingError> {
     let sample_count = stream.len();
     let total_time = stream.last().map_or(0.0, |r| r.timestamp.as_secs_f32());
     let avg_delta = total_time / sample_count as f32;
@@ -282,7 +1114,8 @@ fn process_ahrs(stream: &[Record]) -> Result<Vec<UnitQuaternion<f32>>, Convertin
     let mut previous = None;
     for mut record in stream {
         if record.sample.acc == [0.0, 0.0, 0.0] {
-            record = previous.ok_or(ConvertingError::UnrecoverableSensorData)?;
+            record =
+                previous.ok_or_else(|| ConvertingError::UnrecoverableSensorData(sensor.into()))?;
         } else {
             previous = Some(record);
         }
@@ -291,34 +1124,27 @@ fn process_ahrs(stream: &[Record]) -> Result<Vec<UnitQuaternion<f32>>, Convertin
         let accel = record.sample.acc.clone().into();
         let q_ahrs = ahrs
             .update_imu(&gyro, &accel)
-            .map_err(ConvertingError::AhrsError)?;
+            .map_err(|err| ConvertingError::AhrsError(sensor.into(), err))?;
         rotations.push(*q_ahrs);
     }
     Ok(rotations)
 }
 
-struct NodeTree {
-    index: Index<Node>,
-    children: Vec<NodeTree>,
+pub(crate) struct NodeTree {
+    pub index: Index<Node>,
+    pub children: Vec<NodeTree>,
 }
 impl NodeTree {
-    fn new(root: &Root) -> Vec<NodeTree> {
-        let skin = root.skins.iter().next().unwrap();
-        let all_nodes = skin.joints.clone();
+    pub fn new(root: &Root, joints: &[Index<Node>]) -> Result<Vec<NodeTree>, ConvertingError> {
+        let all_nodes = joints.to_vec();
 
-        let mut with_children: HashMap<Index<Node>, Vec<Index<Node>>> = all_nodes
-            .iter()
-            .cloned()
-            .map(|index| {
-                let children = root
-                    .get(index)
-                    .unwrap()
-                    .children
-                    .clone()
-                    .unwrap_or_default();
-                (index, children)
-            })
-            .collect();
+        let mut with_children: HashMap<Index<Node>, Vec<Index<Node>>> = HashMap::new();
+        for index in all_nodes.iter().cloned() {
+            let mut children = get_node(root, index)?.children.clone().unwrap_or_default();
+            // meshes or props attached to a joint are not animated
+            children.retain(|child| all_nodes.contains(child));
+            with_children.insert(index, children);
+        }
 
         let children: HashSet<_> = with_children
             .values()
@@ -335,7 +1161,7 @@ impl NodeTree {
             .into_iter()
             .map(|root| NodeTree::build_tree(root, &mut with_children))
             .collect();
-        result
+        Ok(result)
     }
 
     fn build_tree(
@@ -355,7 +1181,11 @@ impl NodeTree {
         }
     }
 
-    fn for_each_df<F>(&self, f: &mut F)
+    pub fn contains(&self, index: Index<Node>) -> bool {
+        self.index == index || self.children.iter().any(|child| child.contains(index))
+    }
+
+    pub fn for_each_df<F>(&self, f: &mut F)
     where
         F: FnMut(&Self),
     {
@@ -365,7 +1195,7 @@ impl NodeTree {
         f(self);
     }
 
-    fn for_each_bf<F>(&self, f: &mut F)
+    pub fn for_each_bf<F>(&self, f: &mut F)
     where
         F: FnMut(&Self),
     {
@@ -376,53 +1206,457 @@ impl NodeTree {
     }
 }
 
-struct Calibrator {
-    q_local_to_global: UnitQuaternion<f32>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        export,
+        fixtures::{recording, rig, stream, try_convert_with},
+        to_bvh::{self, BvhOptions},
+    };
 
-impl Calibrator {
-    fn new(reference_model: &Model) -> Result<HashMap<String, Calibrator>, ConvertingError> {
-        let mut calibrators = HashMap::new();
+    fn with_rig(rig: Rig) -> ConvertOptions {
+        ConvertOptions {
+            rig,
+            ..Default::default()
+        }
+    }
 
-        for (sensor, stream) in reference_model {
-            // get "down"
-            let stationary_time = 0.5;
-            let approx_y: Vector3<f32> = stream
-                .iter()
-                .map_while(|r| {
-                    (r.timestamp.as_secs_f32() < stationary_time)
-                        .then_some(Vector3::from(r.sample.acc))
-                })
-                .sum();
-            let approx_y = -approx_y.normalize();
-            // get rotation -> axis
-            let new_z = process_ahrs(stream)?
-                .last()
-                .unwrap()
-                .axis()
-                .unwrap()
-                .into_inner(); // already normalized
-            // crossproduct third axis
-            let new_x = new_z.cross(&approx_y).normalize();
-            // orthogonalize and normalize
-            let new_y = new_z.cross(&new_x).normalize();
-
-            // convert to quaternion
-            let rotation_matrix =
-                Rotation3::from_matrix(&Matrix3::from_columns(&[new_x, new_y, new_z]));
-            let quat = UnitQuaternion::from_rotation_matrix(&rotation_matrix);
-
-            calibrators.insert(
-                sensor.into(),
-                Calibrator {
-                    q_local_to_global: quat,
-                },
+    fn try_convert(root: Root, model: &Model) -> Result<Vec<ConvertingWarning>, ConvertingError> {
+        try_convert_with(
+            root,
+            model,
+            &Calibration::default(),
+            &ConvertOptions::default(),
+        )
+    }
+
+    #[test]
+    fn convert_valid() {
+        let warnings = try_convert(rig(), &recording()).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn empty_recording() {
+        let result = try_convert(rig(), &Vec::new());
+        assert!(matches!(result, Err(ConvertingError::EmptyRecording)));
+    }
+
+    #[test]
+    fn sensor_without_samples() {
+        let model = vec![("Upper".into(), Vec::new())];
+        let result = try_convert(rig(), &model);
+        assert!(matches!(result, Err(ConvertingError::NoSamples(sensor)) if sensor == "Upper"));
+    }
+
+    #[test]
+    fn sample_count_mismatch() {
+        let mut model = recording();
+        model[1].1.truncate(50);
+        let result = try_convert(rig(), &model);
+        assert!(matches!(
+            result,
+            Err(ConvertingError::SampleCountMismatch {
+                expected: 100,
+                found: 50,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rig_without_skin() {
+        let mut root = rig();
+        root.skins.clear();
+        let result = try_convert(root, &recording());
+        assert!(matches!(result, Err(ConvertingError::NoSkin)));
+    }
+
+    #[test]
+    fn skin_with_missing_node() {
+        let mut root = rig();
+        root.skins[0].joints.push(gltf::json::Index::new(7));
+        let result = try_convert(root, &recording());
+        assert!(matches!(result, Err(ConvertingError::NodeMissing(7))));
+    }
+
+    #[test]
+    fn sensor_not_coupled() {
+        let mut model = recording();
+        model.push(("Spare".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])));
+        let result = try_convert(rig(), &model);
+        assert!(
+            matches!(result, Err(ConvertingError::SensorNotCoupled(sensor)) if sensor == "Spare")
+        );
+
+        let options = ConvertOptions {
+            skip_uncoupled: true,
+            ..Default::default()
+        };
+        let warnings = try_convert_with(rig(), &model, &Calibration::default(), &options).unwrap();
+        assert!(matches!(
+            warnings.as_slice(),
+            [ConvertingWarning::SensorSkipped(sensor)] if sensor == "Spare"
+        ));
+    }
+
+    #[test]
+    fn unrecoverable_sensor_data() {
+        let mut model = recording();
+        model[0].1[0].sample.acc = [0.0; 3];
+        let result = try_convert(rig(), &model);
+        assert!(
+            matches!(result, Err(ConvertingError::UnrecoverableSensorData(sensor)) if sensor == "Upper")
+        );
+    }
+
+    #[test]
+    fn ahrs_failure() {
+        // not exactly zero, so it is not recovered, but its norm underflows
+        let mut model = recording();
+        model[1].1[5].sample.acc = [f32::MIN_POSITIVE, 0.0, 0.0];
+        let result = try_convert(rig(), &model);
+        assert!(
+            matches!(result, Err(ConvertingError::AhrsError(sensor, AhrsError::AccelerometerNormZero)) if sensor == "Lower")
+        );
+    }
+
+    #[test]
+    fn rig_by_skin_name() {
+        let mut root = rig();
+        root.skins.insert(0, root.skins[0].clone());
+        root.skins[0].joints.truncate(1);
+        root.skins[1].name = Some("Arm".into());
+
+        let options = with_rig(Rig::Skin("Arm".into()));
+        let joints = options.rig.joints(&root).unwrap();
+        assert_eq!(joints.len(), 2);
+        try_convert_with(
+            root.clone(),
+            &recording(),
+            &Calibration::default(),
+            &options,
+        )
+        .unwrap();
+
+        let options = with_rig(Rig::Skin("Leg".into()));
+        let result = try_convert_with(root, &recording(), &Calibration::default(), &options);
+        assert!(matches!(result, Err(ConvertingError::SkinNotFound(skin)) if skin == "Leg"));
+    }
+
+    #[test]
+    fn rig_by_root_node() {
+        let mut root = rig();
+        root.skins.clear();
+
+        let joints = Rig::Node("Upper".into()).joints(&root).unwrap();
+        let joints: Vec<_> = joints.into_iter().map(|index| index.value()).collect();
+        assert_eq!(joints, [0, 1, 2]);
+
+        let options = with_rig(Rig::Node("Upper".into()));
+        let root = convert(
+            root,
+            "Test.bin",
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap()
+        .root;
+        assert_eq!(root.animations[0].channels.len(), 3);
+    }
+
+    #[test]
+    fn rig_missing_root_node() {
+        let options = with_rig(Rig::Node("Base".into()));
+        let result = try_convert_with(rig(), &recording(), &Calibration::default(), &options);
+        assert!(matches!(result, Err(ConvertingError::RootNodeNotFound(node)) if node == "Base"));
+    }
+
+    #[test]
+    fn rig_cyclic_hierarchy() {
+        let mut root = rig();
+        root.nodes[1].children = Some(vec![gltf::json::Index::new(0)]);
+        let joints = Rig::Node("Lower".into()).joints(&root).unwrap();
+        assert_eq!(joints.len(), 3);
+    }
+
+    #[test]
+    fn convert_resampled() {
+        let options = ConvertOptions {
+            frame_rate: FrameRate::Uniform(60),
+            ..Default::default()
+        };
+        let conversion = convert(
+            rig(),
+            "Test.bin",
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap();
+        // 0.99 s of recording at 60 fps
+        let root = &conversion.root;
+        let input = &root.accessors[root.animations[0].samplers[0].input.value()];
+        assert_eq!(input.count.0, 60);
+        assert_eq!(conversion.bin.len(), 60 * 4 + 2 * 60 * 16);
+
+        let (bvh, _) = to_bvh::convert(
+            &rig(),
+            &recording(),
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+            &BvhOptions::default(),
+        )
+        .unwrap();
+        assert!(bvh.contains("Frames: 60\nFrame Time: 0.016667\n"));
+    }
+
+    #[test]
+    fn convert_reduced() {
+        let mut model = recording();
+        // Lower is still in its rest pose relative to Upper
+        model[1].1 = model[0].1.clone();
+        let options = ConvertOptions {
+            reduction: KeyReduction::Tolerance(0.5),
+            ..Default::default()
+        };
+        let conversion = convert(
+            rig(),
+            "Test.bin",
+            &model,
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap();
+
+        let report = conversion.reduction.unwrap();
+        let root = &conversion.root;
+        let animation = &root.animations[0];
+        assert_eq!(animation.channels.len(), 1);
+        assert_eq!(report.tracks_skipped, 1);
+        assert_eq!(
+            report.bytes_saved + conversion.bin.len(),
+            (100 + 2 * 100 * 4) * 4
+        );
+
+        // Upper rotates with constant speed, so its ends are enough
+        let input = &root.accessors[animation.samplers[0].input.value()];
+        assert_eq!(input.count.0, 2);
+        assert_eq!(report.keys_removed, 98 + 100);
+    }
+
+    #[test]
+    fn convert_interpolation() {
+        use gltf::animation::Interpolation;
+        for (interpolation, expected, values_per_key) in [
+            (KeyInterpolation::Step, Interpolation::Step, 1usize),
+            (KeyInterpolation::Linear, Interpolation::Linear, 1),
+            (KeyInterpolation::CubicSpline, Interpolation::CubicSpline, 3),
+        ] {
+            let options = ConvertOptions {
+                interpolation,
+                ..Default::default()
+            };
+            let conversion = convert(
+                rig(),
+                "Test.bin",
+                &recording(),
+                &Calibration::default(),
+                &Mapping::default(),
+                &options,
+            )
+            .unwrap();
+
+            let root = &conversion.root;
+            for sampler in &root.animations[0].samplers {
+                let input = &root.accessors[sampler.input.value()];
+                let output = &root.accessors[sampler.output.value()];
+                assert_eq!(output.count.0, input.count.0 * values_per_key as u64);
+            }
+            assert_eq!(
+                conversion.bin.len(),
+                100 * 4 + 2 * 100 * values_per_key * 16
             );
+
+            let glb =
+                export::to_glb(conversion.root, &[("Test.bin".into(), conversion.bin)]).unwrap();
+            let gltf = gltf::Gltf::from_slice(&glb).unwrap();
+            let sampler = gltf.animations().next().unwrap().samplers().next().unwrap();
+            assert_eq!(sampler.interpolation(), expected);
         }
-        Ok(calibrators)
     }
 
-    fn calibrate(&self, q: UnitQuaternion<f32>) -> UnitQuaternion<f32> {
-        self.q_local_to_global.inverse() * q * self.q_local_to_global
+    #[test]
+    fn convert_several_takes() {
+        let short: Model = recording()
+            .into_iter()
+            .map(|(sensor, stream)| (sensor, stream[..50].to_vec()))
+            .collect();
+        let options = ConvertOptions {
+            reduction: KeyReduction::Tolerance(0.5),
+            ..Default::default()
+        };
+        let conversion = convert_takes(
+            rig(),
+            "Test.bin",
+            &[
+                Take {
+                    name: "Walk",
+                    model: &recording(),
+                },
+                Take {
+                    name: "Jump",
+                    model: &short,
+                },
+            ],
+            &Calibration::default(),
+            &Mapping::default(),
+            &options,
+        )
+        .unwrap();
+        assert!(conversion.reduction.is_some());
+
+        let bins = vec![("Test.bin".to_string(), conversion.bin)];
+        let glb = export::to_glb(conversion.root, &bins).unwrap();
+        let gltf = gltf::Gltf::from_slice(&glb).unwrap();
+        let names: Vec<_> = gltf
+            .animations()
+            .map(|a| a.name().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["Walk", "Jump"]);
+
+        let blob = gltf.blob.as_ref().unwrap();
+        let views: Vec<_> = gltf.views().collect();
+        assert!(
+            views
+                .iter()
+                .any(|view| view.name() == Some("Jump timestamps"))
+        );
+        for view in &views {
+            assert!(view.offset() + view.length() <= blob.len());
+        }
+        // every take reads its own keyframe times
+        for (animation, count) in gltf.animations().zip([100, 50]) {
+            for channel in animation.channels() {
+                let input = channel.sampler().input();
+                assert!(input.count() <= count);
+                let view = input.view().unwrap();
+                let prefix = animation.name().unwrap();
+                assert!(view.name().unwrap().starts_with(prefix));
+            }
+        }
+    }
+
+    #[test]
+    fn convert_still_takes() {
+        let still = vec![
+            ("Upper".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+            ("Lower".into(), stream(100, [0.0, 0.0, 1.0], [0.0; 3])),
+        ];
+        let options = ConvertOptions {
+            reduction: KeyReduction::Tolerance(0.5),
+            provenance: Some(Provenance {
+                generator: "Axon test".into(),
+                recordings: vec!["Idle.amcx".into(), "Walk.amcx".into()],
+                calibration: None,
+            }),
+            ..Default::default()
+        };
+        let takes = [
+            Take {
+                name: "Idle",
+                model: &still,
+            },
+            Take {
+                name: "Walk",
+                model: &recording(),
+            },
+        ];
+        let convert = |takes| {
+            convert_takes(
+                rig(),
+                "Test.bin",
+                takes,
+                &Calibration::default(),
+                &Mapping::default(),
+                &options,
+            )
+            .unwrap()
+        };
+
+        // a take at rest adds no animation, views or buffer
+        let conversion = convert(&takes[..1]);
+        let report = conversion.reduction.unwrap();
+        assert_eq!(report.tracks_skipped, 2);
+        assert_eq!(report.bytes_saved, (100 + 2 * 100 * 4) * 4);
+        assert!(conversion.bin.is_empty());
+        let root = &conversion.root;
+        assert!(root.animations.is_empty() && root.buffer_views.is_empty());
+        assert!(root.accessors.is_empty() && root.buffers.is_empty());
+
+        let conversion = convert(&takes);
+        let root = &conversion.root;
+        assert_eq!(root.animations.len(), 1);
+        assert_eq!(root.animations[0].name.as_deref(), Some("Walk"));
+        let raw = root.animations[0].extras.as_ref().unwrap().get();
+        assert!(raw.contains("Walk.amcx") && !raw.contains("Idle.amcx"));
+        for view in &root.buffer_views {
+            assert!(view.byte_length.0 > 0, "{:?}", view.name);
+        }
+    }
+
+    #[test]
+    fn root_translation_in_world_frame() {
+        // still, accelerating along x, braking and still again
+        let lower = (0..100)
+            .map(|i| {
+                let acc = match i {
+                    20..40 => [0.5, 0.0, 1.0],
+                    40..60 => [-0.5, 0.0, 1.0],
+                    _ => [0.0, 0.0, 1.0],
+                };
+                Record {
+                    timestamp: std::time::Duration::from_millis(10 * i as u64),
+                    sample: Sample { acc, gyr: [0.0; 3] },
+                }
+            })
+            .collect();
+        let model = vec![("Lower".into(), lower)];
+        let mut root = rig();
+        let rest = Vector3::new(0.0, 0.0, 1.0);
+        root.nodes[0].translation = Some(rest.into());
+        let translations = |world| {
+            let options = ConvertOptions {
+                world,
+                ..Default::default()
+            };
+            let root_motion = RootMotion::new("Lower".into());
+            let (joint, translations) =
+                root_translation(&model, &root, &Mapping::default(), &options, &root_motion)
+                    .unwrap();
+            assert_eq!(joint.value(), 0);
+            translations
+        };
+
+        // the displacement is in metres like the rest offset it is added to
+        let z_up = translations(WorldFrame::ZUp);
+        let unreal = translations(WorldFrame::Unreal);
+        assert!((unreal[0] - rest).norm() < 1e-6);
+        // 0.5 g for 0.2 s, then braking for 0.2 s
+        let moved = z_up.last().unwrap() - rest;
+        assert!(
+            (moved.x - 0.5 * root_motion::GRAVITY * 0.2 * 0.2).abs() < 0.02,
+            "{moved}"
+        );
+        for (unreal, z_up) in unreal.iter().zip(&z_up) {
+            let expected = rest + WorldFrame::Unreal.matrix() * (z_up - rest);
+            assert!((unreal - expected).norm() < 1e-6);
+        }
     }
 }
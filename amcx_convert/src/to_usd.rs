@@ -0,0 +1,263 @@
+use std::fmt::Write;
+
+use amcx_core::Model;
+use gltf::json::{Index, Node, Root};
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+
+use crate::{
+    calibration::Calibration,
+    mapping::Mapping,
+    to_gltf::{
+        ConvertOptions, ConvertingError, ConvertingWarning, Motion, NodeTree, get_node,
+        sample_motion,
+    },
+    tracks::FrameRate,
+};
+
+/// Writes the motion as a text USD stage with a `Skeleton` and its `SkelAnimation`
+///
+/// The rest pose is taken from the rig of the target model, one time code is one recorded sample
+pub fn convert(
+    gltf_model: &Root,
+    amcx_model: &Model,
+    calibration: &Calibration,
+    mapping: &Mapping,
+    options: &ConvertOptions,
+) -> Result<(String, Vec<ConvertingWarning>), ConvertingError> {
+    let mut warnings = Vec::new();
+    let Motion {
+        times,
+        rotations,
+        translation,
+    } = sample_motion(
+        amcx_model,
+        gltf_model,
+        calibration,
+        mapping,
+        options,
+        &mut warnings,
+    )?;
+
+    let frame_count = times.len();
+    let rate = match options.frame_rate {
+        FrameRate::Uniform(fps) => fps as f64,
+        FrameRate::Recorded => {
+            let (first, last) = (times.first(), times.last());
+            let duration = last.zip(first).map_or(0.0, |(last, first)| last - first) as f64;
+            match duration > 0.0 {
+                // recorded timestamps jitter, so the rate is kept to a sensible precision
+                true => ((frame_count - 1) as f64 / duration * 1000.0).round() / 1000.0,
+                false => 1.0,
+            }
+        }
+    };
+
+    let joints = options.rig.joints(gltf_model)?;
+    let tree = NodeTree::new(gltf_model, &joints)?;
+    let mut skeleton = Vec::new();
+    for tree_root in &tree {
+        collect_joints(
+            gltf_model,
+            tree_root,
+            None,
+            Matrix4::identity(),
+            &mut skeleton,
+        )?;
+    }
+
+    let identity = vec![UnitQuaternion::identity(); frame_count];
+    let tracks: Vec<_> = skeleton
+        .iter()
+        .map(|joint| rotations.get(&joint.index).unwrap_or(&identity))
+        .collect();
+
+    let mut usda = String::new();
+    writeln!(usda, "#usda 1.0").unwrap();
+    writeln!(usda, "(").unwrap();
+    writeln!(usda, "    defaultPrim = \"Root\"").unwrap();
+    writeln!(usda, "    upAxis = \"{}\"", options.world.up_axis()).unwrap();
+    writeln!(usda, "    metersPerUnit = 1").unwrap();
+    writeln!(usda, "    startTimeCode = 0").unwrap();
+    writeln!(usda, "    endTimeCode = {}", frame_count - 1).unwrap();
+    writeln!(usda, "    timeCodesPerSecond = {rate}").unwrap();
+    writeln!(usda, "    framesPerSecond = {rate}").unwrap();
+    writeln!(usda, ")").unwrap();
+    writeln!(usda).unwrap();
+    writeln!(usda, "def SkelRoot \"Root\"").unwrap();
+    writeln!(usda, "{{").unwrap();
+    writeln!(usda, "    def Skeleton \"Skeleton\" (").unwrap();
+    writeln!(usda, "        prepend apiSchemas = [\"SkelBindingAPI\"]").unwrap();
+    writeln!(usda, "    )").unwrap();
+    writeln!(usda, "    {{").unwrap();
+
+    let joint_paths = array(skeleton.iter().map(|joint| format!("\"{}\"", joint.path)));
+    let bind = array(skeleton.iter().map(|joint| matrix(&joint.bind)));
+    let rest = array(skeleton.iter().map(|joint| matrix(&joint.rest)));
+    writeln!(usda, "        uniform token[] joints = {joint_paths}").unwrap();
+    writeln!(usda, "        uniform matrix4d[] bindTransforms = {bind}").unwrap();
+    writeln!(usda, "        uniform matrix4d[] restTransforms = {rest}").unwrap();
+    writeln!(
+        usda,
+        "        rel skel:animationSource = </Root/Skeleton/Animation>"
+    )
+    .unwrap();
+    writeln!(usda).unwrap();
+    writeln!(usda, "        def SkelAnimation \"Animation\"").unwrap();
+    writeln!(usda, "        {{").unwrap();
+
+    let scales = array(skeleton.iter().map(|joint| vector(&joint.scale)));
+    writeln!(usda, "            uniform token[] joints = {joint_paths}").unwrap();
+    match translation {
+        Some((root_joint, root_translations)) => {
+            writeln!(usda, "            float3[] translations.timeSamples = {{").unwrap();
+            for frame in 0..frame_count {
+                let frame_translations = array(skeleton.iter().map(|joint| {
+                    match (joint.index == root_joint, root_translations.get(frame)) {
+                        (true, Some(translation)) => vector(translation),
+                        _ => vector(&joint.translation),
+                    }
+                }));
+                writeln!(usda, "                {frame}: {frame_translations},").unwrap();
+            }
+            writeln!(usda, "            }}").unwrap();
+        }
+        None => {
+            let translations = array(skeleton.iter().map(|joint| vector(&joint.translation)));
+            writeln!(usda, "            float3[] translations = {translations}").unwrap();
+        }
+    }
+    writeln!(usda, "            half3[] scales = {scales}").unwrap();
+    writeln!(usda, "            quatf[] rotations.timeSamples = {{").unwrap();
+    for frame in 0..frame_count {
+        let frame_rotations = array(tracks.iter().map(|track| {
+            let q = track.get(frame).copied().unwrap_or_default();
+            format!("({}, {}, {}, {})", q.w, q.i, q.j, q.k)
+        }));
+        writeln!(usda, "                {frame}: {frame_rotations},").unwrap();
+    }
+    writeln!(usda, "            }}").unwrap();
+    writeln!(usda, "        }}").unwrap();
+    writeln!(usda, "    }}").unwrap();
+    writeln!(usda, "}}").unwrap();
+
+    Ok((usda, warnings))
+}
+
+struct SkeletonJoint {
+    index: Index<Node>,
+    /// Joint path in the skeleton, like `Pelvis/Chest/Head`
+    path: String,
+    /// Rest transform relative to the parent joint
+    rest: Matrix4<f32>,
+    /// Rest transform relative to the skeleton
+    bind: Matrix4<f32>,
+    translation: Vector3<f32>,
+    scale: Vector3<f32>,
+}
+
+/// Lists the joints parents first, as USD requires
+fn collect_joints(
+    root: &Root,
+    tree: &NodeTree,
+    parent: Option<&str>,
+    parent_bind: Matrix4<f32>,
+    joints: &mut Vec<SkeletonJoint>,
+) -> Result<(), ConvertingError> {
+    let node = get_node(root, tree.index)?;
+    let name = joint_name(node, tree.index);
+    let path = match parent {
+        Some(parent) => format!("{parent}/{name}"),
+        None => name,
+    };
+
+    let translation = Vector3::from(node.translation.unwrap_or_default());
+    let scale = Vector3::from(node.scale.unwrap_or([1.0; 3]));
+    let rotation = node.rotation.map_or(UnitQuaternion::identity(), |r| {
+        UnitQuaternion::from_quaternion(r.0.into())
+    });
+    let rest = match node.matrix {
+        Some(matrix) => Matrix4::from_column_slice(&matrix),
+        None => {
+            Matrix4::new_translation(&translation)
+                * rotation.to_homogeneous()
+                * Matrix4::new_nonuniform_scaling(&scale)
+        }
+    };
+    let bind = parent_bind * rest;
+
+    joints.push(SkeletonJoint {
+        index: tree.index,
+        path: path.clone(),
+        rest,
+        bind,
+        translation: rest.fixed_view::<3, 1>(0, 3).into(),
+        scale,
+    });
+    for child in &tree.children {
+        collect_joints(root, child, Some(&path), bind, joints)?;
+    }
+    Ok(())
+}
+
+/// USD joint names are path elements, so anything but identifier characters is replaced
+fn joint_name(node: &Node, index: Index<Node>) -> String {
+    match &node.name {
+        Some(name) => name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect(),
+        None => format!("Node{}", index.value()),
+    }
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(", "))
+}
+
+fn vector(v: &Vector3<f32>) -> String {
+    format!("({}, {}, {})", v.x, v.y, v.z)
+}
+
+/// USD matrices are row-major with the translation in the last row,
+/// which is the column-major layout of glTF and nalgebra
+fn matrix(m: &Matrix4<f32>) -> String {
+    let rows = m.column_iter().map(|column| {
+        let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+        format!("({a}, {b}, {c}, {d})")
+    });
+    format!("( {} )", rows.collect::<Vec<_>>().join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{recording, rig};
+
+    #[test]
+    fn export_usd() {
+        let mut model = recording();
+        // 100 samples over 1.98 s make 50 time codes per second
+        model
+            .iter_mut()
+            .flat_map(|(_, stream)| stream)
+            .for_each(|record| {
+                record.timestamp *= 2;
+            });
+        let (usda, _) = convert(
+            &rig(),
+            &model,
+            &Calibration::default(),
+            &Mapping::default(),
+            &ConvertOptions::default(),
+        )
+        .unwrap();
+
+        assert!(usda.starts_with("#usda 1.0\n"));
+        assert!(usda.contains("timeCodesPerSecond = 50\n"));
+        assert!(usda.contains("endTimeCode = 99\n"));
+        assert!(usda.contains("uniform token[] joints = [\"Upper\", \"Upper/Lower\"]"));
+        assert!(usda.contains("                99: [("));
+        assert!(!usda.contains("                100: [("));
+        assert_eq!(usda.matches('{').count(), usda.matches('}').count());
+    }
+}
@@ -0,0 +1,463 @@
+use std::fmt::Display;
+
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+
+/// Frame rate of the exported animation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameRate {
+    /// One keyframe per recorded sample, at the recorded timestamps
+    #[default]
+    Recorded,
+    /// Evenly spaced keyframes, this many per second
+    Uniform(u32),
+}
+impl FrameRate {
+    pub const ALL: [FrameRate; 4] = [
+        FrameRate::Recorded,
+        FrameRate::Uniform(30),
+        FrameRate::Uniform(60),
+        FrameRate::Uniform(120),
+    ];
+}
+impl Display for FrameRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameRate::Recorded => write!(f, "Recorded rate"),
+            FrameRate::Uniform(fps) => write!(f, "{fps} fps"),
+        }
+    }
+}
+
+/// Interpolation between the exported keyframes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyInterpolation {
+    /// Holds every key until the next one, shows the raw samples
+    Step,
+    #[default]
+    Linear,
+    /// Smooth curve through the keys, with tangents from the neighbouring keys
+    CubicSpline,
+}
+impl KeyInterpolation {
+    pub const ALL: [KeyInterpolation; 3] = [
+        KeyInterpolation::Step,
+        KeyInterpolation::Linear,
+        KeyInterpolation::CubicSpline,
+    ];
+}
+impl Display for KeyInterpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyInterpolation::Step => write!(f, "Step"),
+            KeyInterpolation::Linear => write!(f, "Linear"),
+            KeyInterpolation::CubicSpline => write!(f, "Cubic spline"),
+        }
+    }
+}
+
+/// Keyframe reduction of the exported tracks
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum KeyReduction {
+    #[default]
+    Off,
+    /// Maximum angle in degrees between the reduced and the full track
+    Tolerance(f32),
+}
+impl KeyReduction {
+    pub const ALL: [KeyReduction; 5] = [
+        KeyReduction::Off,
+        KeyReduction::Tolerance(0.1),
+        KeyReduction::Tolerance(0.5),
+        KeyReduction::Tolerance(1.0),
+        KeyReduction::Tolerance(2.0),
+    ];
+}
+impl Display for KeyReduction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyReduction::Off => write!(f, "No reduction"),
+            KeyReduction::Tolerance(degrees) => write!(f, "Reduce within {degrees}°"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReductionReport {
+    pub keys_removed: usize,
+    /// Constant tracks that match the rest pose and were not written at all
+    pub tracks_skipped: usize,
+    pub bytes_saved: usize,
+}
+impl Display for ReductionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Removed {} keys and {} constant tracks, saving {:.1} KiB",
+            self.keys_removed,
+            self.tracks_skipped,
+            self.bytes_saved as f32 / 1024.0
+        )
+    }
+}
+
+/// Rotation keyframes of a single joint
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub times: Vec<f32>,
+    pub rotations: Vec<UnitQuaternion<f32>>,
+}
+
+/// Evenly spaced times from `start` up to `end`
+pub fn uniform_times(start: f32, end: f32, fps: u32) -> Vec<f32> {
+    let fps = fps.max(1) as f32;
+    let count = ((end - start).max(0.0) * fps + 1e-3).floor() as usize + 1;
+    (0..count).map(|i| start + i as f32 / fps).collect()
+}
+
+/// Samples the track at sorted `new_times` with SLERP between the neighbouring keys,
+/// holding the first and last key outside of the track
+pub fn resample(
+    times: &[f32],
+    rotations: &[UnitQuaternion<f32>],
+    new_times: &[f32],
+) -> Vec<UnitQuaternion<f32>> {
+    let len = times.len().min(rotations.len());
+    if len == 0 {
+        return Vec::new();
+    }
+
+    let mut next = 0;
+    new_times
+        .iter()
+        .map(|&t| {
+            while next < len && times[next] < t {
+                next += 1;
+            }
+            match next {
+                0 => rotations[0],
+                n if n == len => rotations[len - 1],
+                n => interpolate(
+                    (times[n - 1], &rotations[n - 1]),
+                    (times[n], &rotations[n]),
+                    t,
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Samples the values at sorted `new_times` with linear interpolation, like [`resample`]
+pub fn resample_vectors(
+    times: &[f32],
+    values: &[Vector3<f32>],
+    new_times: &[f32],
+) -> Vec<Vector3<f32>> {
+    let len = times.len().min(values.len());
+    if len == 0 {
+        return Vec::new();
+    }
+
+    let mut next = 0;
+    new_times
+        .iter()
+        .map(|&t| {
+            while next < len && times[next] < t {
+                next += 1;
+            }
+            match next {
+                0 => values[0],
+                n if n == len => values[len - 1],
+                n if times[n] <= times[n - 1] => values[n],
+                n => values[n - 1].lerp(&values[n], (t - times[n - 1]) / (times[n] - times[n - 1])),
+            }
+        })
+        .collect()
+}
+
+/// Whether every rotation is within `tolerance` radians of `q`
+pub fn is_constant(
+    rotations: &[UnitQuaternion<f32>],
+    q: &UnitQuaternion<f32>,
+    tolerance: f32,
+) -> bool {
+    rotations.iter().all(|r| r.angle_to(q) <= tolerance)
+}
+
+/// Drops the keys that `interpolation` between the remaining ones reproduces
+/// within `tolerance` radians
+///
+/// A constant track is reduced to a single key.
+pub fn reduce(track: &Track, tolerance: f32, interpolation: KeyInterpolation) -> Track {
+    let len = track.times.len().min(track.rotations.len());
+    if len == 0 {
+        return Track::default();
+    }
+    if is_constant(&track.rotations[..len], &track.rotations[0], tolerance) {
+        return Track {
+            times: vec![track.times[0]],
+            rotations: vec![track.rotations[0]],
+        };
+    }
+
+    // Ramer-Douglas-Peucker with the angle to the interpolated rotation as the distance,
+    // a whole level at a time, since spline tangents depend on the neighbouring keys
+    let mut keep = vec![false; len];
+    keep[0] = true;
+    keep[len - 1] = true;
+    loop {
+        let kept = || (0..len).filter(|i| keep[*i]);
+        let reduced = Track {
+            times: kept().map(|i| track.times[i]).collect(),
+            rotations: kept().map(|i| track.rotations[i]).collect(),
+        };
+        let reproduced = evaluate(&reduced, &track.times[..len], interpolation);
+
+        let mut added = false;
+        let mut worst: Option<(usize, f32)> = None;
+        for i in 0..len {
+            if keep[i] {
+                if let Some((worst, _)) = worst.take().filter(|(_, error)| *error > tolerance) {
+                    keep[worst] = true;
+                    added = true;
+                }
+                continue;
+            }
+            let error = reproduced[i].angle_to(&track.rotations[i]);
+            if worst.is_none_or(|(_, worst)| error > worst) {
+                worst = Some((i, error));
+            }
+        }
+        if !added {
+            return reduced;
+        }
+    }
+}
+
+/// Samples the track at sorted `times` the way a glTF viewer interpolates its keys,
+/// holding the first and last key outside of the track
+pub fn evaluate(
+    track: &Track,
+    times: &[f32],
+    interpolation: KeyInterpolation,
+) -> Vec<UnitQuaternion<f32>> {
+    match interpolation {
+        KeyInterpolation::Linear => resample(&track.times, &track.rotations, times),
+        KeyInterpolation::Step => {
+            let len = track.times.len().min(track.rotations.len());
+            if len == 0 {
+                return Vec::new();
+            }
+            let mut next = 0;
+            times
+                .iter()
+                .map(|&t| {
+                    while next < len && track.times[next] <= t {
+                        next += 1;
+                    }
+                    track.rotations[next.saturating_sub(1)]
+                })
+                .collect()
+        }
+        KeyInterpolation::CubicSpline => {
+            let spline = cubic_spline(track);
+            let len = spline.len() / 3;
+            if len == 0 {
+                return Vec::new();
+            }
+            let value = |i: usize| UnitQuaternion::new_normalize(spline[3 * i + 1]);
+            let mut next = 0;
+            times
+                .iter()
+                .map(|&t| {
+                    while next < len && track.times[next] < t {
+                        next += 1;
+                    }
+                    match next {
+                        0 => value(0),
+                        n if n == len => value(len - 1),
+                        n if track.times[n] <= track.times[n - 1] => value(n),
+                        n => {
+                            let delta = track.times[n] - track.times[n - 1];
+                            let s = (t - track.times[n - 1]) / delta;
+                            let (s2, s3) = (s * s, s * s * s);
+                            let q = spline[3 * (n - 1) + 1] * (2.0 * s3 - 3.0 * s2 + 1.0)
+                                + spline[3 * (n - 1) + 2] * ((s3 - 2.0 * s2 + s) * delta)
+                                + spline[3 * n + 1] * (-2.0 * s3 + 3.0 * s2)
+                                + spline[3 * n] * ((s3 - s2) * delta);
+                            UnitQuaternion::new_normalize(q)
+                        }
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+fn interpolate(
+    (t0, q0): (f32, &UnitQuaternion<f32>),
+    (t1, q1): (f32, &UnitQuaternion<f32>),
+    t: f32,
+) -> UnitQuaternion<f32> {
+    // jittery timestamps may repeat
+    if t1 <= t0 {
+        return *q1;
+    }
+    q0.try_slerp(q1, (t - t0) / (t1 - t0), f32::EPSILON)
+        .unwrap_or(*q1)
+}
+
+/// Flips every rotation into the hemisphere of its predecessor,
+/// so that interpolating the components takes the short way
+pub fn align_hemispheres(rotations: &mut [UnitQuaternion<f32>]) {
+    for i in 1..rotations.len() {
+        if rotations[i].coords.dot(&rotations[i - 1].coords) < 0.0 {
+            rotations[i] = UnitQuaternion::new_unchecked(-rotations[i].into_inner());
+        }
+    }
+}
+
+/// In-tangent, value and out-tangent of every key, as glTF lays out cubic spline outputs
+///
+/// Tangents are the Catmull-Rom derivatives per second, one-sided at the ends of the track.
+pub fn cubic_spline(track: &Track) -> Vec<Quaternion<f32>> {
+    let len = track.times.len().min(track.rotations.len());
+    let mut rotations = track.rotations[..len].to_vec();
+    align_hemispheres(&mut rotations);
+
+    let mut spline = Vec::with_capacity(len * 3);
+    for i in 0..len {
+        let (prev, next) = (i.saturating_sub(1), (i + 1).min(len - 1));
+        let dt = track.times[next] - track.times[prev];
+        let tangent = match dt > 0.0 {
+            true => (rotations[next].into_inner() - rotations[prev].into_inner()) / dt,
+            false => Quaternion::new(0.0, 0.0, 0.0, 0.0),
+        };
+        spline.extend([tangent, rotations[i].into_inner(), tangent]);
+    }
+    spline
+}
+
+/// Cubic spline outputs of vector keys, laid out like [`cubic_spline`]
+pub fn cubic_spline_vectors(times: &[f32], values: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
+    let len = times.len().min(values.len());
+    let mut spline = Vec::with_capacity(len * 3);
+    for i in 0..len {
+        let (prev, next) = (i.saturating_sub(1), (i + 1).min(len - 1));
+        let dt = times[next] - times[prev];
+        let tangent = match dt > 0.0 {
+            true => (values[next] - values[prev]) / dt,
+            false => Vector3::zeros(),
+        };
+        spline.extend([tangent, values[i], tangent]);
+    }
+    spline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::noisy_track;
+
+    #[test]
+    fn resample_uniform() {
+        let times = uniform_times(0.0, 1.0, 30);
+        assert_eq!(times.len(), 31);
+        assert!((times[30] - 1.0).abs() < 1e-6);
+
+        let a = UnitQuaternion::identity();
+        let b = UnitQuaternion::from_euler_angles(0.0, 0.0, 1.0);
+        // repeated timestamps step between keys
+        let resampled = resample(
+            &[0.0, 0.5, 0.5, 1.0],
+            &[a, a, b, b],
+            &[-1.0, 0.25, 0.75, 2.0],
+        );
+        assert_eq!(resampled, [a, a, b, b]);
+
+        let resampled = resample(&[0.0, 1.0], &[a, b], &[0.25]);
+        assert!((resampled[0].angle() - 0.25).abs() < 1e-5);
+
+        // q and -q are the same rotation, interpolation takes the short way
+        let c = UnitQuaternion::new_unchecked(-b.into_inner());
+        let resampled = resample(&[0.0, 1.0], &[a, c], &[0.5]);
+        assert!((resampled[0].angle() - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reduce_track() {
+        // rotates about z with constant speed, then stops
+        let times: Vec<_> = (0..100).map(|i| i as f32 * 0.01).collect();
+        let rotations: Vec<_> = times
+            .iter()
+            .map(|t| UnitQuaternion::from_euler_angles(0.0, 0.0, t.min(0.5)))
+            .collect();
+        let track = Track { times, rotations };
+
+        let reduced = reduce(&track, 0.1_f32.to_radians(), KeyInterpolation::Linear);
+        let times = &track.times;
+        assert_eq!(reduced.times, [times[0], times[50], times[99]]);
+
+        // the reduced track stays within the tolerance as it is played back
+        let tolerance = 0.5_f32.to_radians();
+        let curve = Track {
+            times: times.clone(),
+            rotations: times
+                .iter()
+                .map(|t| UnitQuaternion::from_euler_angles(0.3 * t, 0.0, (3.0 * t).sin()))
+                .collect(),
+        };
+        // splines overshoot where the ramp stops
+        for (track, interpolation) in [&track, &curve]
+            .into_iter()
+            .flat_map(|track| KeyInterpolation::ALL.map(|interpolation| (track, interpolation)))
+        {
+            let reduced = reduce(track, tolerance, interpolation);
+            assert!(reduced.times.len() < times.len(), "{interpolation}");
+            let played = evaluate(&reduced, times, interpolation);
+            for (q, expected) in played.iter().zip(&track.rotations) {
+                assert!(q.angle_to(expected) <= tolerance, "{interpolation}");
+            }
+        }
+
+        let constant = Track {
+            times: vec![0.0, 1.0, 2.0],
+            rotations: vec![track.rotations[0]; 3],
+        };
+        assert_eq!(
+            reduce(&constant, 0.0, KeyInterpolation::Linear).times,
+            [0.0]
+        );
+        let empty = reduce(&Track::default(), 0.1, KeyInterpolation::CubicSpline);
+        assert_eq!(empty, Track::default());
+    }
+
+    #[test]
+    fn cubic_spline_tangents() {
+        let a = UnitQuaternion::identity();
+        let b = UnitQuaternion::from_euler_angles(0.0, 0.0, 0.2);
+        // -b is the same rotation as b
+        let c = UnitQuaternion::new_unchecked(-b.into_inner());
+        let track = Track {
+            times: vec![0.0, 0.5, 1.0],
+            rotations: vec![a, c, a],
+        };
+        let spline = cubic_spline(&track);
+        assert_eq!(spline.len(), 9);
+        assert_eq!(spline[4], b.into_inner());
+        assert_eq!(spline[3], spline[5]);
+        // symmetric track, so the middle tangent is flat
+        assert!(spline[3].norm() < 1e-6);
+        assert!((spline[0] - (b.into_inner() - a.into_inner()) / 0.5).norm() < 1e-6);
+    }
+
+    #[test]
+    fn hemisphere_continuity() {
+        let (_, mut rotations) = noisy_track();
+        align_hemispheres(&mut rotations);
+        assert!(
+            rotations
+                .windows(2)
+                .all(|q| q[0].coords.dot(&q[1].coords) > 0.0)
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt::Display;
+
+use nalgebra::{Matrix3, Quaternion, Unit, UnitQuaternion, Vector3};
+
+/// Axis convention of the world of the target model, which the AHRS world frame is carried into.
+///
+/// The AHRS fuses the sensors in a right-handed Z-up frame, which after the heading alignment
+/// is taken as X forward and Y left. Joint rotations are conjugated into the target frame,
+/// the root translation is mapped, staying in metres like the rest of the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorldFrame {
+    /// Right-handed, Z up, X forward, in metres: the AHRS frame as it is
+    #[default]
+    ZUp,
+    /// Right-handed, Y up, Z forward, in metres: the glTF convention
+    YUp,
+    /// Left-handed, Z up, X forward, Y right, in metres
+    Unreal,
+    /// Left-handed, Y up, Z forward, X right, in metres
+    Unity,
+}
+impl WorldFrame {
+    pub const ALL: [WorldFrame; 4] = [
+        WorldFrame::ZUp,
+        WorldFrame::YUp,
+        WorldFrame::Unreal,
+        WorldFrame::Unity,
+    ];
+
+    /// Images of the AHRS axes as columns
+    pub fn matrix(&self) -> Matrix3<f32> {
+        let (x, y, z) = match self {
+            WorldFrame::ZUp => (Vector3::x(), Vector3::y(), Vector3::z()),
+            WorldFrame::YUp => (Vector3::z(), Vector3::x(), Vector3::y()),
+            WorldFrame::Unreal => (Vector3::x(), -Vector3::y(), Vector3::z()),
+            WorldFrame::Unity => (Vector3::z(), -Vector3::x(), Vector3::y()),
+        };
+        Matrix3::from_columns(&[x, y, z])
+    }
+
+    pub fn is_left_handed(&self) -> bool {
+        matches!(self, WorldFrame::Unreal | WorldFrame::Unity)
+    }
+
+    /// Vertical axis, like `Y`
+    pub fn up_axis(&self) -> char {
+        match self {
+            WorldFrame::ZUp | WorldFrame::Unreal => 'Z',
+            WorldFrame::YUp | WorldFrame::Unity => 'Y',
+        }
+    }
+
+    /// Rotation in the AHRS world frame expressed in this frame
+    ///
+    /// The rotation axis is a pseudovector, so a change of handedness flips it
+    /// and the rotation keeps its sense in the mirrored frame.
+    pub fn rotation(&self, q: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+        let axis = self.matrix() * q.imag() * self.handedness();
+        UnitQuaternion::new_unchecked(Quaternion::from_parts(q.w, axis))
+    }
+
+    /// Rotation in this frame expressed in the AHRS world frame, the inverse of [`Self::rotation`]
+    pub fn rotation_to_ahrs(&self, q: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+        let axis = self.matrix().transpose() * q.imag() * self.handedness();
+        UnitQuaternion::new_unchecked(Quaternion::from_parts(q.w, axis))
+    }
+
+    /// Displacement in the AHRS world frame expressed in this frame
+    pub fn displacement(&self, v: &Vector3<f32>) -> Vector3<f32> {
+        self.matrix() * v
+    }
+
+    /// Rotation axis in this frame expressed in the AHRS world frame
+    pub fn axis_to_ahrs(&self, axis: &Unit<Vector3<f32>>) -> Unit<Vector3<f32>> {
+        Unit::new_unchecked(self.matrix().transpose() * axis.into_inner() * self.handedness())
+    }
+
+    fn handedness(&self) -> f32 {
+        match self.is_left_handed() {
+            true => -1.0,
+            false => 1.0,
+        }
+    }
+}
+impl Display for WorldFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldFrame::ZUp => write!(f, "Z-up"),
+            WorldFrame::YUp => write!(f, "Y-up (glTF)"),
+            WorldFrame::Unreal => write!(f, "Unreal"),
+            WorldFrame::Unity => write!(f, "Unity"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        fixtures::{rig, stream},
+        mapping::Mapping,
+        to_gltf::{ConvertOptions, calculate_rotations},
+        to_usd,
+    };
+
+    #[test]
+    fn world_frame_axes() {
+        let yaw = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.4);
+        let pitch = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -0.7);
+        for frame in WorldFrame::ALL {
+            let det = frame.matrix().determinant();
+            assert!((det - if frame.is_left_handed() { -1.0 } else { 1.0 }).abs() < 1e-6);
+            // converting rotations keeps their composition
+            let composed = frame.rotation(&(yaw * pitch));
+            assert!(composed.angle_to(&(frame.rotation(&yaw) * frame.rotation(&pitch))) < 1e-5);
+        }
+
+        // yaw turns around the vertical axis of the frame
+        let y_up = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.4);
+        assert!(WorldFrame::YUp.rotation(&yaw).angle_to(&y_up) < 1e-6);
+        let unreal = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -0.4);
+        assert!(WorldFrame::Unreal.rotation(&yaw).angle_to(&unreal) < 1e-6);
+        let unity = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -0.4);
+        assert!(WorldFrame::Unity.rotation(&yaw).angle_to(&unity) < 1e-6);
+
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(WorldFrame::ZUp.displacement(&v), v);
+        assert_eq!(
+            WorldFrame::YUp.displacement(&v),
+            Vector3::new(2.0, 3.0, 1.0)
+        );
+        assert_eq!(
+            WorldFrame::Unreal.displacement(&v),
+            Vector3::new(1.0, -2.0, 3.0)
+        );
+        assert_eq!(
+            WorldFrame::Unity.displacement(&v),
+            Vector3::new(-2.0, 3.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn convert_in_world_frame() {
+        // turning around gravity
+        let model = vec![
+            (
+                "Upper".into(),
+                stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, 0.5]),
+            ),
+            (
+                "Lower".into(),
+                stream(100, [0.0, 0.0, 1.0], [0.0, 0.0, 0.0]),
+            ),
+        ];
+        let upper_axis = |world| {
+            let options = ConvertOptions {
+                world,
+                ..Default::default()
+            };
+            let rotations = calculate_rotations(
+                &model,
+                &rig(),
+                &Calibration::default(),
+                &Mapping::default(),
+                &options,
+                &mut Vec::new(),
+            )
+            .unwrap();
+            let last = rotations[&gltf::json::Index::new(0)].last().unwrap();
+            last.axis().unwrap().into_inner() * last.angle().signum()
+        };
+        assert!((upper_axis(WorldFrame::ZUp) - Vector3::z()).norm() < 1e-4);
+        assert!((upper_axis(WorldFrame::YUp) - Vector3::y()).norm() < 1e-4);
+        assert!((upper_axis(WorldFrame::Unity) + Vector3::y()).norm() < 1e-4);
+
+        let usd = |world| {
+            let options = ConvertOptions {
+                world,
+                ..Default::default()
+            };
+            let (usda, _) = to_usd::convert(
+                &rig(),
+                &model,
+                &Calibration::default(),
+                &Mapping::default(),
+                &options,
+            )
+            .unwrap();
+            usda
+        };
+        assert!(usd(WorldFrame::YUp).contains("upAxis = \"Y\""));
+        let unreal = usd(WorldFrame::Unreal);
+        assert!(unreal.contains("upAxis = \"Z\"") && unreal.contains("metersPerUnit = 1"));
+    }
+}
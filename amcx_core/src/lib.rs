@@ -25,10 +25,13 @@ pub mod processed {
 pub mod raw {
     use std::time::Duration;
 
-    #[derive(Debug, Clone, PartialEq, Eq)]
+    use crate::mounting::Mounting;
+
+    #[derive(Debug, Clone, PartialEq)]
     pub struct File {
         pub config: Config,
         pub sensors: Vec<Sensor>,
+        pub mountings: Vec<(Sensor, Mounting)>,
         pub clusters: Vec<Cluster>,
     }
 
@@ -209,3 +212,230 @@ pub mod raw {
         ];
     }
 }
+
+pub mod mounting {
+    use std::{fmt::Display, str::FromStr};
+
+    /// Text that is no signed axis, axis remap or mounting
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ParseMountingError(pub String);
+    impl Display for ParseMountingError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "invalid mounting {}", self.0)
+        }
+    }
+    impl std::error::Error for ParseMountingError {}
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Axis {
+        X,
+        Y,
+        Z,
+    }
+    impl Axis {
+        pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+        const fn index(&self) -> usize {
+            match self {
+                Axis::X => 0,
+                Axis::Y => 1,
+                Axis::Z => 2,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct SignedAxis {
+        pub axis: Axis,
+        pub negative: bool,
+    }
+    impl FromStr for SignedAxis {
+        type Err = ParseMountingError;
+
+        fn from_str(val: &str) -> Result<Self, Self::Err> {
+            let invalid = || ParseMountingError(val.into());
+            let (negative, axis) = match val.split_at_checked(1).ok_or_else(invalid)? {
+                ("+", axis) => (false, axis),
+                ("-", axis) => (true, axis),
+                _ => (false, val),
+            };
+            let axis = match axis.to_ascii_lowercase().as_str() {
+                "x" => Axis::X,
+                "y" => Axis::Y,
+                "z" => Axis::Z,
+                _ => return Err(invalid()),
+            };
+            Ok(SignedAxis { axis, negative })
+        }
+    }
+    impl Display for SignedAxis {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let sign = if self.negative { '-' } else { '+' };
+            let axis = match self.axis {
+                Axis::X => 'x',
+                Axis::Y => 'y',
+                Axis::Z => 'z',
+            };
+            write!(f, "{sign}{axis}")
+        }
+    }
+
+    /// Sensor axes as seen by the converter, e.g. `+x -z +y`
+    /// takes converter's y from the sensor's -z and converter's z from the sensor's y.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct AxisRemap(pub [SignedAxis; 3]);
+    impl AxisRemap {
+        pub const IDENTITY: AxisRemap = AxisRemap([
+            SignedAxis {
+                axis: Axis::X,
+                negative: false,
+            },
+            SignedAxis {
+                axis: Axis::Y,
+                negative: false,
+            },
+            SignedAxis {
+                axis: Axis::Z,
+                negative: false,
+            },
+        ]);
+
+        /// All 24 remaps that keep the frame right-handed
+        pub fn all() -> Vec<AxisRemap> {
+            let mut all = Vec::with_capacity(24);
+            for x in Axis::ALL {
+                for y in Axis::ALL.into_iter().filter(|y| *y != x) {
+                    let z = Axis::ALL.into_iter().find(|z| *z != x && *z != y).unwrap();
+                    for signs in 0..8 {
+                        let signed = |axis, bit: u8| SignedAxis {
+                            axis,
+                            negative: signs & bit != 0,
+                        };
+                        let remap = AxisRemap([signed(x, 1), signed(y, 2), signed(z, 4)]);
+                        if remap.is_proper() {
+                            all.push(remap);
+                        }
+                    }
+                }
+            }
+            all
+        }
+
+        /// Every axis is used once and handedness is preserved
+        pub fn is_proper(&self) -> bool {
+            let [x, y, z] = self.0.map(|s| {
+                let mut v = [0i32; 3];
+                v[s.axis.index()] = if s.negative { -1 } else { 1 };
+                v
+            });
+            let cross = [
+                x[1] * y[2] - x[2] * y[1],
+                x[2] * y[0] - x[0] * y[2],
+                x[0] * y[1] - x[1] * y[0],
+            ];
+            cross == z
+        }
+
+        pub fn apply(&self, v: [f32; 3]) -> [f32; 3] {
+            self.0.map(|s| {
+                let value = v[s.axis.index()];
+                if s.negative { -value } else { value }
+            })
+        }
+    }
+    impl FromStr for AxisRemap {
+        type Err = ParseMountingError;
+
+        fn from_str(val: &str) -> Result<Self, Self::Err> {
+            let invalid = || ParseMountingError(val.into());
+            let mut chars = val.chars().filter(|c| !c.is_whitespace()).peekable();
+            let mut axes = [AxisRemap::IDENTITY.0[0]; 3];
+            for axis in &mut axes {
+                let sign = chars.next_if(|c| *c == '+' || *c == '-');
+                let name = chars.next().ok_or_else(invalid)?;
+                *axis = format!("{}{name}", sign.unwrap_or('+'))
+                    .parse()
+                    .map_err(|_| invalid())?;
+            }
+            let remap = AxisRemap(axes);
+            if chars.next().is_some() || !remap.is_proper() {
+                return Err(invalid());
+            }
+            Ok(remap)
+        }
+    }
+    impl Default for AxisRemap {
+        fn default() -> Self {
+            AxisRemap::IDENTITY
+        }
+    }
+    impl Display for AxisRemap {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let [x, y, z] = &self.0;
+            write!(f, "{x} {y} {z}")
+        }
+    }
+
+    /// How a sensor is strapped onto its segment,
+    /// applied to acc and gyr before any orientation estimation.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Mounting {
+        /// Axis permutation with sign flips, e.g. `+x-z+y`
+        Axes(AxisRemap),
+        /// Arbitrary fixed rotation as roll, pitch, yaw in degrees, e.g. `rpy:90,0,180`
+        Rotation([f32; 3]),
+    }
+    impl Mounting {
+        pub const ROTATION_PREFIX: &str = "rpy:";
+
+        pub fn apply(&self, v: [f32; 3]) -> [f32; 3] {
+            match self {
+                Mounting::Axes(axes) => axes.apply(v),
+                Mounting::Rotation([roll, pitch, yaw]) => {
+                    let (sr, cr) = roll.to_radians().sin_cos();
+                    let (sp, cp) = pitch.to_radians().sin_cos();
+                    let (sy, cy) = yaw.to_radians().sin_cos();
+                    // yaw * pitch * roll
+                    let m = [
+                        [cy * cp, cy * sp * sr - sy * cr, cy * sp * cr + sy * sr],
+                        [sy * cp, sy * sp * sr + cy * cr, sy * sp * cr - cy * sr],
+                        [-sp, cp * sr, cp * cr],
+                    ];
+                    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+                }
+            }
+        }
+    }
+    impl FromStr for Mounting {
+        type Err = ParseMountingError;
+
+        fn from_str(val: &str) -> Result<Self, Self::Err> {
+            let invalid = || ParseMountingError(val.into());
+            match val.strip_prefix(Self::ROTATION_PREFIX) {
+                Some(angles) => {
+                    let angles: Vec<f32> = angles
+                        .split(',')
+                        .map(|a| a.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid())?;
+                    let rotation: [f32; 3] = angles.try_into().map_err(|_| invalid())?;
+                    if rotation.iter().any(|a| !a.is_finite()) {
+                        return Err(invalid());
+                    }
+                    Ok(Mounting::Rotation(rotation))
+                }
+                None => val.parse().map(Mounting::Axes),
+            }
+        }
+    }
+    impl Display for Mounting {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Mounting::Axes(AxisRemap([x, y, z])) => write!(f, "{x}{y}{z}"),
+                Mounting::Rotation([roll, pitch, yaw]) => {
+                    write!(f, "{}{roll},{pitch},{yaw}", Self::ROTATION_PREFIX)
+                }
+            }
+        }
+    }
+}
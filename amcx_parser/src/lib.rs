@@ -1,6 +1,6 @@
 mod raw_parsing;
 pub mod parsing_error;
-pub use raw_parsing::raw_parse;
+pub use raw_parsing::{raw_parse, raw_parse_mountings, write_mountings};
 
 mod processing;
 pub use processing::parse;
@@ -38,6 +38,12 @@ pub enum InnerParsingError {
         key: String,
         valid_values: Vec<&'static str>,
     },
+    #[error("unknown sensor in mountings: {0}")]
+    MountingUnknownSensor(String),
+    #[error("duplicate mountings are not allowed: {0}")]
+    MountingDuplicate(String),
+    #[error("invalid mounting {0}, expected axes like +x-z+y or a rotation like rpy:90,0,180")]
+    MountingInvalid(String),
     #[error(transparent)]
     NumberParsing(#[from] ParseIntError),
 }
@@ -5,11 +5,14 @@ use amcx_core::{Model, Record, Sample, Stream};
 
 use crate::{parsing_error::ParsingError, raw_parse};
 
+/// Resolves the samples as recorded,
+/// the mountings of the header are applied by the converter's mapping
 pub fn parse(source: &str) -> Result<Model, ParsingError> {
     let File {
         config,
         sensors,
         clusters,
+        ..
     } = raw_parse(source)?;
 
     let mut model: Model = sensors
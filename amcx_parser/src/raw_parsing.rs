@@ -1,4 +1,5 @@
 use crate::parsing_error::*;
+use amcx_core::mounting::Mounting;
 use amcx_core::raw::*;
 
 enum ConfigKV {
@@ -44,10 +45,19 @@ pub fn raw_parse(source: &str) -> Result<File, ParsingError> {
         .lines()
         .map(str::trim)
         .enumerate()
-        .filter_map(|(i, s)| (!s.is_empty()).then_some((i + 1, s)));
+        .filter_map(|(i, s)| (!s.is_empty()).then_some((i + 1, s)))
+        .peekable();
 
     let config = block(parse_config, source.next(), ("?[", "]"))?;
     let sensors: Vec<Sensor> = block(parse_sensors, source.next(), ("&[", "]"))?;
+    let mountings = match source.next_if(|(_, s)| s.starts_with(MOUNTINGS_BLOCK.0)) {
+        Some(line) => block(
+            |s| parse_mountings(s, &sensors),
+            Some(line),
+            MOUNTINGS_BLOCK,
+        )?,
+        None => Vec::new(),
+    };
     let mut clusters = Vec::new();
 
     while let Some((line, delta)) = source.next() {
@@ -67,10 +77,94 @@ pub fn raw_parse(source: &str) -> Result<File, ParsingError> {
     Ok(File {
         config,
         sensors,
+        mountings,
         clusters,
     })
 }
 
+const MOUNTINGS_BLOCK: (&str, &str) = ("@[", "]");
+
+/// Reads only the optional mountings block of the header
+pub fn raw_parse_mountings(source: &str) -> Result<Vec<(Sensor, Mounting)>, ParsingError> {
+    let header: Vec<_> = source
+        .lines()
+        .filter(|s| !s.trim().is_empty())
+        .take(3)
+        .filter(|s| {
+            ["?[", "&[", MOUNTINGS_BLOCK.0]
+                .iter()
+                .any(|b| s.trim().starts_with(b))
+        })
+        .collect();
+    raw_parse(&header.join("\n")).map(|file| file.mountings)
+}
+
+/// Replaces the mountings block of the header, keeping the rest of the source intact
+pub fn write_mountings(source: &str, mountings: &[(Sensor, Mounting)]) -> String {
+    let block = match mountings.is_empty() {
+        true => None,
+        false => {
+            let mountings: Vec<_> = mountings
+                .iter()
+                .map(|(sensor, mounting)| format!("{sensor}={mounting}"))
+                .collect();
+            Some(format!(
+                "{} {} {}",
+                MOUNTINGS_BLOCK.0,
+                mountings.join(" "),
+                MOUNTINGS_BLOCK.1
+            ))
+        }
+    };
+
+    // offsets of the header lines, which keep their line endings
+    let mut header = Vec::new();
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        if !line.trim().is_empty() {
+            header.push((offset, line));
+            if header.len() == 3 {
+                break;
+            }
+        }
+        offset += line.len();
+    }
+
+    let mut result = source.to_owned();
+    let newline = if source.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    match header.get(2) {
+        Some((start, line)) if line.trim().starts_with(MOUNTINGS_BLOCK.0) => match block {
+            Some(block) => {
+                let content = line.trim_end_matches(['\r', '\n']);
+                result.replace_range(*start..start + content.len(), &block);
+            }
+            None => result.replace_range(*start..start + line.len(), ""),
+        },
+        _ => {
+            let Some(block) = block else {
+                return result;
+            };
+            // after the sensors
+            match header.get(1) {
+                Some((start, line)) if line.ends_with('\n') => {
+                    result.insert_str(start + line.len(), &format!("{block}{newline}"))
+                }
+                _ => {
+                    if !result.is_empty() && !result.ends_with('\n') {
+                        result.push_str(newline);
+                    }
+                    result.push_str(&block);
+                }
+            }
+        }
+    }
+    result
+}
+
 fn block<F, O>(
     action: F,
     source: Option<(usize, &str)>,
@@ -160,6 +254,33 @@ fn parse_sensors(source: &str) -> Result<Vec<Sensor>, InnerParsingError> {
     Ok(sensors)
 }
 
+fn parse_mountings(
+    source: &str,
+    sensors: &[Sensor],
+) -> Result<Vec<(Sensor, Mounting)>, InnerParsingError> {
+    let mut mountings: Vec<(Sensor, Mounting)> = Vec::new();
+    for mounting in source.split_whitespace() {
+        let (sensor, value) =
+            mounting
+                .split_once('=')
+                .ok_or_else(|| InnerParsingError::TokenUnexpected {
+                    expected: "SENSOR=MOUNTING".into(),
+                    found: mounting.into(),
+                })?;
+        if !sensors.iter().any(|s| s == sensor) {
+            return Err(InnerParsingError::MountingUnknownSensor(sensor.into()));
+        }
+        if mountings.iter().any(|(s, _)| s == sensor) {
+            return Err(InnerParsingError::MountingDuplicate(sensor.into()));
+        }
+        let value: Mounting = value
+            .parse()
+            .map_err(|_| InnerParsingError::MountingInvalid(value.into()))?;
+        mountings.push((sensor.into(), value));
+    }
+    Ok(mountings)
+}
+
 fn parse_sample(source: &str) -> Result<Sample, InnerParsingError> {
     let mut source = source.split_whitespace();
 
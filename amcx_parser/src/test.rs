@@ -29,6 +29,80 @@ fn reference_invalid() {
     todo!()
 }
 
+#[test]
+fn mounting_valid() {
+    use amcx_core::mounting::Mounting;
+
+    let source = "?[ BITS=16 ACCEL_SR=2 GYRO_SR=250 CLOCK=milli ]\n&[ A B ]\n@[ A=+x-z+y B=rpy:0,0,90 ]\n10\n[ 16384 0 0 0 0 0 ]\n[ 16384 0 0 0 0 0 ]";
+    let mountings = raw_parsing::raw_parse(source).unwrap().mountings;
+    assert_eq!(mountings[0], ("A".into(), "+x -z +y".parse().unwrap()));
+    assert_eq!(
+        mountings[1],
+        ("B".into(), Mounting::Rotation([0.0, 0.0, 90.0]))
+    );
+
+    // the samples stay as recorded, the converter applies the mountings
+    let model = crate::parse(source).unwrap();
+    let [x, y, z] = model[1].1[0].sample.acc;
+    assert_relative_eq!(x, 1.0, epsilon = 1e-6);
+    assert_relative_eq!(y, 0.0, epsilon = 1e-6);
+    assert_relative_eq!(z, 0.0, epsilon = 1e-6);
+    let [x, y, z] = mountings[1].1.apply([x, y, z]);
+    assert_relative_eq!(y, 1.0, epsilon = 1e-6);
+    assert_relative_eq!(x.abs() + z.abs(), 0.0, epsilon = 1e-6);
+
+    let without = raw_parsing::write_mountings(source, &[]);
+    assert!(
+        raw_parsing::raw_parse(&without)
+            .unwrap()
+            .mountings
+            .is_empty()
+    );
+    let restored = raw_parsing::write_mountings(&without, &mountings);
+    assert_eq!(
+        raw_parsing::raw_parse(&restored).unwrap().mountings,
+        mountings
+    );
+}
+
+#[test]
+fn mounting_round_trip() {
+    let header = "?[ BITS=16 ACCEL_SR=2 GYRO_SR=250 CLOCK=milli ]\r\n&[ A B ]\r\n";
+    let block = "@[ A=+x-z+y B=rpy:0,0,90 ]\r\n";
+    let samples = "10\r\n [ 16384 0 0 0 0 0 ]\r\n\t[ 16384 0 0 0 0 0 ]\r\n\r\n";
+    let source = format!("{header}{block}{samples}");
+    let mountings = raw_parsing::raw_parse(&source).unwrap().mountings;
+
+    // only the mountings block changes, line endings and the trailing newline stay
+    assert_eq!(raw_parsing::write_mountings(&source, &mountings), source);
+    let without = raw_parsing::write_mountings(&source, &[]);
+    assert_eq!(without, format!("{header}{samples}"));
+    assert_eq!(raw_parsing::write_mountings(&without, &mountings), source);
+    let changed = raw_parsing::write_mountings(&source, &mountings[..1]);
+    assert_eq!(changed, format!("{header}@[ A=+x-z+y ]\r\n{samples}"));
+
+    let unix = source.replace("\r\n", "\n");
+    let without = raw_parsing::write_mountings(&unix, &[]);
+    assert_eq!(raw_parsing::write_mountings(&without, &mountings), unix);
+}
+
+#[test]
+fn mounting_invalid() {
+    let header = "?[ BITS=16 ACCEL_SR=2 GYRO_SR=250 CLOCK=milli ]\n&[ A ]\n";
+    let invalid_sensor = format!("{header}@[ B=+x+y+z ]");
+    let invalid_duplicate = format!("{header}@[ A=+x+y+z A=+y+x-z ]");
+    let invalid_left_handed = format!("{header}@[ A=+x+y-z ]");
+    let invalid_rotation = format!("{header}@[ A=rpy:90,0 ]");
+    for source in [
+        invalid_sensor,
+        invalid_duplicate,
+        invalid_left_handed,
+        invalid_rotation,
+    ] {
+        assert!(raw_parsing::raw_parse(&source).is_err());
+    }
+}
+
 #[test]
 fn sample_valid() {
     todo!()
@@ -1,14 +1,26 @@
 use std::{
     cell::LazyCell,
-    io::Write,
+    collections::HashMap,
     path::{Path, PathBuf},
     ptr::write_bytes,
     sync::LazyLock,
 };
 
+use amcx_convert::{
+    angles::AngleReport,
+    calibration::CalibrationMethod,
+    export::{self, ExportError, ExportFormat},
+    mapping::Mapping,
+    retarget::JointMap,
+    scaling::BodyMeasurements,
+    to_gltf::{ConvertOptions, ConvertingWarning},
+    tracks::ReductionReport,
+};
 use amcx_core::Model;
 use charts::{ChartSensor, SensorID};
 use iced::widget::text_editor;
+use nalgebra::{Unit, Vector3};
+use thiserror::Error;
 
 mod charts;
 mod update;
@@ -24,11 +36,26 @@ pub struct State {
     file_hovered: Option<PathBuf>,
     file: Option<File>,
     model: Option<Model>,
+    mounting_drafts: HashMap<String, String>,
     calibration: Option<(Model, PathBuf)>,
+    calibration_method: CalibrationMethod,
+    mapping: Mapping,
+    convert_options: ConvertOptions,
+    export_format: ExportFormat,
     charts: Option<Charts>,
     anim_model: AnimModel,
     chosen_model: DefaultModels,
+    /// User model the motion of the chosen model is retargeted onto
+    target: Option<(PathBuf, AnimModel)>,
+    joint_map: JointMap,
+    /// Proportions of the subject, which the animated model is scaled to
+    measurements: BodyMeasurements,
     converted: Option<Converted>,
+    /// Flexion recordings calibrating the axes of hinge joints
+    hinges: Vec<Hinge>,
+    /// Recordings converted as further animations
+    takes: Vec<(PathBuf, Model)>,
+    rom_channel: Option<RomChannel>,
     selector_visible: bool,
     errors: Errors,
 }
@@ -37,14 +64,25 @@ impl Default for State {
         State {
             anim_model: default_models::HUMAN.clone(),
             chosen_model: DefaultModels::Human,
+            target: None,
+            joint_map: JointMap::default(),
+            measurements: BodyMeasurements::default(),
             tab: TabBar::Editor,
             dialog: false,
             file_hovered: None,
             file: None,
             model: None,
+            mounting_drafts: HashMap::new(),
             calibration: None,
+            calibration_method: CalibrationMethod::Stationary,
+            mapping: Mapping::default(),
+            convert_options: ConvertOptions::default(),
+            export_format: ExportFormat::default(),
             charts: None,
             converted: None,
+            hinges: Vec::new(),
+            takes: Vec::new(),
+            rom_channel: None,
             selector_visible: true,
             errors: Errors {
                 expanded: false,
@@ -81,6 +119,94 @@ pub struct Charts {
 
 pub struct Converted {
     modified: AnimModel,
+    warnings: Vec<ConvertingWarning>,
+    reduction: Option<ReductionReport>,
+    /// Joint angles of the conversion, for the range of motion analysis
+    angles: Option<AngleReport>,
+}
+impl Converted {
+    pub const ANIMATION_BIN: &str = "Animation.bin";
+
+    /// Names the animation buffer after the saved file, so that exports into one folder don't collide
+    fn save(&self, path: &Path, format: ExportFormat) -> Result<(), SaveError> {
+        let mut model = self.modified.clone();
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("Animation");
+        export::rename_buffer(
+            &mut model.gltf,
+            &mut model.bins,
+            Converted::ANIMATION_BIN,
+            &format!("{stem}.bin"),
+        );
+        model.write_to(path, format)
+    }
+}
+
+/// Flexion recording of a hinge joint, between the sensors of its two segments
+pub struct Hinge {
+    path: PathBuf,
+    recording: Model,
+    parent: String,
+    child: String,
+    axis: HingeAxis,
+}
+
+/// Axis of the child joint a hinge turns around
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HingeAxis {
+    X,
+    Y,
+    Z,
+}
+impl HingeAxis {
+    pub const ALL: [HingeAxis; 3] = [HingeAxis::X, HingeAxis::Y, HingeAxis::Z];
+
+    fn vector(self) -> Unit<Vector3<f32>> {
+        match self {
+            HingeAxis::X => Vector3::x_axis(),
+            HingeAxis::Y => Vector3::y_axis(),
+            HingeAxis::Z => Vector3::z_axis(),
+        }
+    }
+}
+impl std::fmt::Display for HingeAxis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HingeAxis::X => write!(f, "X Axis"),
+            HingeAxis::Y => write!(f, "Y Axis"),
+            HingeAxis::Z => write!(f, "Z Axis"),
+        }
+    }
+}
+
+/// Joint angle analysed for range of motion and repetitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomChannel {
+    pub joint: String,
+    pub axis: char,
+}
+impl std::fmt::Display for RomChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.joint, self.axis)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Export(#[from] ExportError),
+}
+
+#[derive(Debug, Error)]
+pub enum OpenModelError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Gltf(#[from] gltf::Error),
 }
 
 #[derive(Clone)]
@@ -90,22 +216,49 @@ pub struct AnimModel {
 }
 
 impl AnimModel {
-    fn write_to(&self, path: &Path) -> Result<(), std::io::Error> {
-        let gltf_file = std::fs::File::options()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(path)?;
-        self.gltf.to_writer_pretty(gltf_file)?;
-
-        let path = path.parent().unwrap();
-        for (name, bin) in &self.bins {
-            let mut bin_file = std::fs::File::options()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(path.join(name))?;
-            bin_file.write_all(bin)?
+    /// Loads a `.gltf` with its buffer files, or a `.glb` whose binary chunk becomes a buffer file named after it
+    fn open(path: &Path) -> Result<AnimModel, OpenModelError> {
+        let gltf::Gltf { document, mut blob } = gltf::Gltf::open(path)?;
+        let mut gltf = document.into_json();
+        let folder = path.parent().unwrap_or(Path::new(""));
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("Model");
+
+        let mut bins = Vec::new();
+        for buffer in &mut gltf.buffers {
+            match buffer.uri.as_deref() {
+                None => {
+                    let name = format!("{stem}.bin");
+                    bins.push((name.clone(), blob.take().unwrap_or_default()));
+                    buffer.uri = Some(name);
+                }
+                Some(uri) if uri.starts_with("data:") => {}
+                Some(uri) => bins.push((uri.to_string(), std::fs::read(folder.join(uri))?)),
+            }
+        }
+        Ok(AnimModel { gltf, bins })
+    }
+
+    /// Builds the whole export before writing, so that a failing export leaves an existing file intact
+    fn write_to(&self, path: &Path, format: ExportFormat) -> Result<(), SaveError> {
+        let content = match format {
+            ExportFormat::Separate => self.gltf.to_vec_pretty().map_err(ExportError::from)?,
+            ExportFormat::Embedded => {
+                let mut gltf = self.gltf.clone();
+                export::embed(&mut gltf, &self.bins)?;
+                gltf.to_vec_pretty().map_err(ExportError::from)?
+            }
+            ExportFormat::Binary => export::to_glb(self.gltf.clone(), &self.bins)?,
+        };
+        std::fs::write(path, content)?;
+
+        if format == ExportFormat::Separate {
+            let path = path.parent().unwrap();
+            for (name, bin) in &self.bins {
+                std::fs::write(path.join(name), bin)?;
+            }
         }
         Ok(())
     }
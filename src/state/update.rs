@@ -1,5 +1,29 @@
-use amcx_parser::{parse as amcx_parse, parsing_error::ParsingError};
+use amcx_convert::{
+    angles::AngleOptions,
+    calibration::{Calibration, CalibrationMethod, HingeCalibration},
+    export::ExportFormat,
+    heading::HeadingCorrection,
+    limits::JointLimits,
+    mapping::{Coupling, Mapping},
+    provenance::Provenance,
+    retarget::{JointMap, Retarget},
+    rom::{self, RangeOfMotion, RepetitionOptions},
+    root_motion::RootMotion,
+    scaling::{self, BodyMeasurements, ScalingError},
+    smoothing::Filter,
+    to_bvh::BvhOptions,
+    to_gltf::{ConvertOptions, ConvertingError, Rig, Take},
+    tracks::{FrameRate, KeyInterpolation, KeyReduction},
+    world_frame::WorldFrame,
+};
+use amcx_core::mounting::Mounting;
+use amcx_parser::{
+    parse as amcx_parse,
+    parsing_error::{InnerParsingError, ParsingError},
+    raw_parse_mountings, write_mountings,
+};
 use std::{
+    fmt::Display,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -71,6 +95,46 @@ impl Into<Message> for PlottingMessage {
 pub enum ConvertingDialog {
     Save,
     Calibration,
+    OpenHinge,
+    OpenMapping,
+    SaveMapping,
+    OpenLimits,
+    SaveMotion(MotionFormat),
+    SaveRom,
+    OpenTake,
+    OpenTarget,
+    OpenJointMap,
+    OpenMeasurements,
+}
+
+/// Motion formats written straight from the recording, besides glTF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionFormat {
+    Bvh,
+    Usd,
+    /// Joint angle time series
+    Angles,
+    /// Joint angle statistics
+    AngleSummary,
+}
+impl MotionFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            MotionFormat::Bvh => "bvh",
+            MotionFormat::Usd => "usda",
+            MotionFormat::Angles | MotionFormat::AngleSummary => "csv",
+        }
+    }
+}
+impl Display for MotionFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MotionFormat::Bvh => write!(f, "BVH"),
+            MotionFormat::Usd => write!(f, "USD"),
+            MotionFormat::Angles => write!(f, "Angles"),
+            MotionFormat::AngleSummary => write!(f, "Angle Summary"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -80,6 +144,44 @@ pub enum ConvertingMessage {
     Save(PathBuf),
     OpenCalibration(PathBuf),
     CalibrationOpened(Arc<String>, PathBuf),
+    CalibrationMethodSelected(CalibrationMethod),
+    OpenHinge(PathBuf),
+    HingeOpened(Arc<String>, PathBuf),
+    HingeParentSelected(usize, String),
+    HingeChildSelected(usize, String),
+    HingeAxisSelected(usize, HingeAxis),
+    ClearHinges,
+    OpenMapping(PathBuf),
+    MappingOpened(Arc<String>),
+    SaveMapping(PathBuf),
+    OpenLimits(PathBuf),
+    LimitsOpened(Arc<String>),
+    SaveMotion(MotionFormat, PathBuf),
+    SaveRom(PathBuf),
+    OpenTake(PathBuf),
+    TakeOpened(Arc<String>, PathBuf),
+    ClearTakes,
+    OpenTarget(PathBuf),
+    OpenJointMap(PathBuf),
+    JointMapOpened(Arc<String>),
+    ClearTarget,
+    OpenMeasurements(PathBuf),
+    MeasurementsOpened(Arc<String>),
+    ClearMeasurements,
+    RomChannelSelected(RomChannel),
+    Coupled(String, Coupling),
+    SkipUncoupled(bool),
+    HeadingCorrection(bool),
+    RigSelected(Rig),
+    ExportFormatSelected(ExportFormat),
+    FrameRateSelected(FrameRate),
+    KeyReductionSelected(KeyReduction),
+    InterpolationSelected(KeyInterpolation),
+    FilterSelected(Filter),
+    WorldFrameSelected(WorldFrame),
+    RootMotionSelected(Option<String>),
+    MountingEdited(String, String),
+    MountingSubmitted(String),
     ModelSelected(DefaultModels),
 }
 impl Into<Message> for ConvertingMessage {
@@ -239,15 +341,276 @@ impl State {
                 }
                 Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
             },
-            Converting::Save(path) if self.converted.is_some() => {
-                match self.converted.as_ref().unwrap().modified.write_to(&path) {
+            Converting::Save(mut path) if self.converted.is_some() => {
+                if path.extension().is_none() {
+                    path.set_extension(self.export_format.extension());
+                }
+                match self
+                    .converted
+                    .as_ref()
+                    .unwrap()
+                    .save(&path, self.export_format)
+                {
                     Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
                     Ok(_) => Task::none(),
                 }
             }
+            Converting::CalibrationMethodSelected(method) => {
+                self.calibration_method = method;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::OpenHinge(path) => Task::future(async move {
+                match read_file(&path).await.map(Arc::new) {
+                    Ok(content) => Converting::HingeOpened(content, path).into(),
+                    Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                }
+            }),
+            Converting::HingeOpened(content, path) => match amcx_parse(&content) {
+                Ok(recording) => {
+                    // the first two sensors until others are selected
+                    let mut sensors = recording.iter().map(|(sensor, _)| sensor.clone());
+                    let parent = sensors.next().unwrap_or_default();
+                    let child = sensors.next().unwrap_or_else(|| parent.clone());
+                    self.hinges.push(Hinge {
+                        path,
+                        recording,
+                        parent,
+                        child,
+                        axis: HingeAxis::X,
+                    });
+                    self.converted = None;
+                    Task::none()
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::HingeParentSelected(index, sensor) => {
+                if let Some(hinge) = self.hinges.get_mut(index) {
+                    hinge.parent = sensor;
+                    self.converted = None;
+                }
+                Task::none()
+            }
+            Converting::HingeChildSelected(index, sensor) => {
+                if let Some(hinge) = self.hinges.get_mut(index) {
+                    hinge.child = sensor;
+                    self.converted = None;
+                }
+                Task::none()
+            }
+            Converting::HingeAxisSelected(index, axis) => {
+                if let Some(hinge) = self.hinges.get_mut(index) {
+                    hinge.axis = axis;
+                    self.converted = None;
+                }
+                Task::none()
+            }
+            Converting::ClearHinges => {
+                self.hinges.clear();
+                self.converted = None;
+                Task::none()
+            }
+            Converting::OpenMapping(path) => Task::future(async move {
+                match read_file(&path).await.map(Arc::new) {
+                    Ok(content) => Converting::MappingOpened(content).into(),
+                    Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                }
+            }),
+            Converting::MappingOpened(content) => match Mapping::parse(&content) {
+                Ok(mapping) => {
+                    self.mapping = mapping;
+                    self.converted = None;
+                    // the mountings of the header take precedence over the mapping file
+                    match self.build_model() {
+                        Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+                        Ok(_) => Task::none(),
+                    }
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::SaveMapping(path) => {
+                let content = self.mapping.to_string();
+                Task::future(async move {
+                    match write_file(&path, &content).await {
+                        Ok(_) => Message::None,
+                        Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                    }
+                })
+            }
+            Converting::OpenLimits(path) => Task::future(async move {
+                match read_file(&path).await.map(Arc::new) {
+                    Ok(content) => Converting::LimitsOpened(content).into(),
+                    Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                }
+            }),
+            Converting::LimitsOpened(content) => match JointLimits::parse(&content) {
+                Ok(limits) => {
+                    self.convert_options.limits = limits;
+                    self.converted = None;
+                    Task::none()
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::SaveRom(mut path) => {
+                let Some(rom) = self.rom() else {
+                    return Task::none();
+                };
+                if path.extension().is_none() {
+                    path.set_extension("csv");
+                }
+                let content = rom.to_csv();
+                Task::future(async move {
+                    match write_file(&path, &content).await {
+                        Ok(_) => Message::None,
+                        Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                    }
+                })
+            }
+            Converting::OpenTake(path) => Task::future(async move {
+                match read_file(&path).await.map(Arc::new) {
+                    Ok(content) => Converting::TakeOpened(content, path).into(),
+                    Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                }
+            }),
+            Converting::TakeOpened(content, path) => match amcx_parse(&content) {
+                Ok(model) => {
+                    self.takes.push((path, model));
+                    self.converted = None;
+                    Task::none()
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::ClearTakes => {
+                self.takes.clear();
+                self.converted = None;
+                Task::none()
+            }
+            Converting::OpenTarget(path) => match AnimModel::open(&path) {
+                Ok(model) => {
+                    self.target = Some((path, model));
+                    self.converted = None;
+                    Task::none()
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::OpenJointMap(path) => Task::future(async move {
+                match read_file(&path).await.map(Arc::new) {
+                    Ok(content) => Converting::JointMapOpened(content).into(),
+                    Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                }
+            }),
+            Converting::JointMapOpened(content) => match JointMap::parse(&content) {
+                Ok(joint_map) => {
+                    self.joint_map = joint_map;
+                    self.converted = None;
+                    Task::none()
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::ClearTarget => {
+                self.target = None;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::OpenMeasurements(path) => Task::future(async move {
+                match read_file(&path).await.map(Arc::new) {
+                    Ok(content) => Converting::MeasurementsOpened(content).into(),
+                    Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                }
+            }),
+            Converting::MeasurementsOpened(content) => match BodyMeasurements::parse(&content) {
+                Ok(measurements) => {
+                    self.measurements = measurements;
+                    self.converted = None;
+                    Task::none()
+                }
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::ClearMeasurements => {
+                self.measurements = BodyMeasurements::default();
+                self.converted = None;
+                Task::none()
+            }
+            Converting::RomChannelSelected(channel) => {
+                self.rom_channel = Some(channel);
+                Task::none()
+            }
+            Converting::SaveMotion(format, path) => match self.convert_motion(format) {
+                Ok(content) => Task::future(async move {
+                    match write_file(&path, &content).await {
+                        Ok(_) => Message::None,
+                        Err(err) => ErrorMessage::Occured(Arc::new(err)).into(),
+                    }
+                }),
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+            },
+            Converting::Coupled(sensor, coupling) => {
+                self.mapping.set(sensor, coupling);
+                self.converted = None;
+                Task::none()
+            }
+            Converting::SkipUncoupled(skip) => {
+                self.convert_options.skip_uncoupled = skip;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::HeadingCorrection(enabled) => {
+                self.convert_options.heading = enabled.then(HeadingCorrection::default);
+                self.converted = None;
+                Task::none()
+            }
+            Converting::FrameRateSelected(frame_rate) => {
+                self.convert_options.frame_rate = frame_rate;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::KeyReductionSelected(reduction) => {
+                self.convert_options.reduction = reduction;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::InterpolationSelected(interpolation) => {
+                self.convert_options.interpolation = interpolation;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::FilterSelected(filter) => {
+                self.convert_options.smoothing.filter = filter;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::WorldFrameSelected(world) => {
+                self.convert_options.world = world;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::RootMotionSelected(sensor) => {
+                self.convert_options.root_motion = sensor.map(RootMotion::new);
+                self.converted = None;
+                Task::none()
+            }
+            Converting::ExportFormatSelected(format) => {
+                self.export_format = format;
+                Task::none()
+            }
+            Converting::RigSelected(rig) => {
+                self.convert_options.rig = rig;
+                self.converted = None;
+                Task::none()
+            }
+            Converting::MountingEdited(sensor, draft) => {
+                self.mounting_drafts.insert(sensor, draft);
+                Task::none()
+            }
+            Converting::MountingSubmitted(sensor) => match self.mount(sensor) {
+                Err(err) => ErrorMessage::Occured(Arc::new(err)).task(),
+                Ok(_) => Task::none(),
+            },
             Converting::ModelSelected(model) => {
                 self.anim_model = model.get();
                 self.chosen_model = model;
+                self.convert_options.rig = Rig::default();
+                self.converted = None;
                 Task::none()
             }
             _ => Task::none(),
@@ -286,11 +649,25 @@ impl State {
         })
     }
     fn convert_dialog(&mut self, action: ConvertingDialog) -> Task<Message> {
-        Task::future(convert_dialog(action)).then(move |opt| match opt {
+        Task::future(convert_dialog(action, self.export_format)).then(move |opt| match opt {
             None => Message::None.task(),
             Some(path) => match action {
                 ConvertingDialog::Calibration => ConvertingMessage::OpenCalibration(path).task(),
+                ConvertingDialog::OpenHinge => ConvertingMessage::OpenHinge(path).task(),
                 ConvertingDialog::Save => ConvertingMessage::Save(path).task(),
+                ConvertingDialog::OpenMapping => ConvertingMessage::OpenMapping(path).task(),
+                ConvertingDialog::SaveMapping => ConvertingMessage::SaveMapping(path).task(),
+                ConvertingDialog::OpenLimits => ConvertingMessage::OpenLimits(path).task(),
+                ConvertingDialog::SaveMotion(format) => {
+                    ConvertingMessage::SaveMotion(format, path).task()
+                }
+                ConvertingDialog::SaveRom => ConvertingMessage::SaveRom(path).task(),
+                ConvertingDialog::OpenTake => ConvertingMessage::OpenTake(path).task(),
+                ConvertingDialog::OpenTarget => ConvertingMessage::OpenTarget(path).task(),
+                ConvertingDialog::OpenJointMap => ConvertingMessage::OpenJointMap(path).task(),
+                ConvertingDialog::OpenMeasurements => {
+                    ConvertingMessage::OpenMeasurements(path).task()
+                }
             },
         })
     }
@@ -298,7 +675,13 @@ impl State {
     fn build_model(&mut self) -> Result<bool, ParsingError> {
         let maybe_source = self.file.as_ref().map(|f| f.content.text());
         if let Some(source) = maybe_source {
-            self.model = Some(amcx_parse(&source)?);
+            let model = amcx_parse(&source)?;
+            // the header keeps the mountings of the recording, the mapping applies them,
+            // sensors without one in the header keep the mounting of the mapping file
+            for (sensor, mounting) in raw_parse_mountings(&source)? {
+                self.mapping.mount(&sensor, Some(mounting));
+            }
+            self.model = Some(model);
             return Ok(true);
         }
         Ok(false)
@@ -312,6 +695,42 @@ impl State {
         Ok(false)
     }
 
+    /// Stores the drafted mounting in the mapping and the header of the working file
+    fn mount(&mut self, sensor: String) -> Result<(), ParsingError> {
+        let Some(draft) = self.mounting_drafts.remove(&sensor) else {
+            return Ok(());
+        };
+        let mounting = match draft.trim() {
+            "" => None,
+            draft => Some(
+                draft
+                    .parse::<Mounting>()
+                    .map_err(|_| InnerParsingError::MountingInvalid(draft.into()))
+                    .map_err(Into::<ParsingError>::into)?,
+            ),
+        };
+
+        self.mapping.mount(&sensor, mounting);
+        self.converted = None;
+
+        let Some(model) = self.model.as_ref() else {
+            return Ok(());
+        };
+        let mountings: Vec<_> = self
+            .mapping
+            .mountings()
+            .into_iter()
+            .filter(|(sensor, _)| model.iter().any(|(s, _)| s == sensor))
+            .collect();
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let source = write_mountings(&file.content.text(), &mountings);
+        file.content = text_editor::Content::with_text(&source);
+        file.modified = true;
+        self.on_file_changed()
+    }
+
     fn on_file_changed(&mut self) -> Result<(), ParsingError> {
         // invalidate previous state
         self.model = None;
@@ -336,25 +755,171 @@ impl State {
         Ok(())
     }
 
+    fn calibration(&self) -> Calibration<'_> {
+        Calibration {
+            reference: self.calibration.as_ref().map(|c| &c.0),
+            method: self.calibration_method,
+            hinges: self
+                .hinges
+                .iter()
+                .map(|hinge| HingeCalibration {
+                    recording: &hinge.recording,
+                    parent: hinge.parent.clone(),
+                    child: hinge.child.clone(),
+                    axis: hinge.axis.vector(),
+                })
+                .collect(),
+        }
+    }
+
+    fn convert_motion(&self, format: MotionFormat) -> Result<String, ScalingError> {
+        let Some(model) = self.model.as_ref() else {
+            return Err(ConvertingError::EmptyRecording.into());
+        };
+        let options = self.retargeted(self.convert_options.clone());
+        let gltf = &self.output_model(&options)?.gltf;
+        let calibration = self.calibration();
+        let (content, _) = match format {
+            MotionFormat::Bvh => amcx_convert::to_bvh::convert(
+                gltf,
+                model,
+                &calibration,
+                &self.mapping,
+                &options,
+                &BvhOptions::default(),
+            )?,
+            MotionFormat::Usd => {
+                amcx_convert::to_usd::convert(gltf, model, &calibration, &self.mapping, &options)?
+            }
+            MotionFormat::Angles | MotionFormat::AngleSummary => {
+                let (report, warnings) = amcx_convert::angles::convert(
+                    gltf,
+                    model,
+                    &calibration,
+                    &self.mapping,
+                    &options,
+                    &AngleOptions::default(),
+                )?;
+                match format {
+                    MotionFormat::Angles => (report.to_csv(), warnings),
+                    _ => (report.summary_csv(), warnings),
+                }
+            }
+        };
+        Ok(content)
+    }
+
+    /// Model the conversion animates: the loaded target, or else the chosen model,
+    /// scaled to the measurements of the subject
+    fn output_model(&self, options: &ConvertOptions) -> Result<AnimModel, ScalingError> {
+        let mut model = self
+            .target
+            .as_ref()
+            .map_or(&self.anim_model, |(_, model)| model)
+            .clone();
+        scaling::scale(
+            &mut model.gltf,
+            &mut model.bins,
+            &options.rig,
+            &self.measurements,
+        )?;
+        Ok(model)
+    }
+
+    /// Retargets the motion of the chosen model onto the first skin of the loaded target
+    fn retargeted(&self, options: ConvertOptions) -> ConvertOptions {
+        if self.target.is_none() {
+            return options;
+        }
+        ConvertOptions {
+            rig: Rig::default(),
+            retarget: Some(Retarget {
+                source: self.anim_model.gltf.clone(),
+                rig: options.rig.clone(),
+                joints: self.joint_map.clone(),
+                match_bones: true,
+            }),
+            ..options
+        }
+    }
+
+    /// Range of motion of the selected joint angle in the current conversion
+    pub(super) fn rom(&self) -> Option<RangeOfMotion> {
+        let angles = self.converted.as_ref()?.angles.as_ref()?;
+        let channel = self.rom_channel.as_ref()?;
+        let series = angles.series(&channel.joint, channel.axis)?;
+        Some(rom::analyze(
+            &angles.times,
+            &series,
+            &RepetitionOptions::default(),
+        ))
+    }
+
     fn convert(&mut self) -> Task<Message> {
         if self.converted.is_some() {
             return ConvertingMessage::Dialog(ConvertingDialog::Save).task();
         }
-        let bin_name = "Animation.bin";
-        match amcx_convert::to_gltf::convert(
-            self.anim_model.gltf.clone(),
+        let bin_name = Converted::ANIMATION_BIN;
+        let path = self
+            .file
+            .as_ref()
+            .map(|file| file.path.clone())
+            .unwrap_or_default();
+        let recordings: Vec<_> = [(&path, self.model.as_ref().unwrap())]
+            .into_iter()
+            .chain(self.takes.iter().map(|(path, model)| (path, model)))
+            .collect();
+        let names: Vec<_> = recordings.iter().map(|(path, _)| take_name(path)).collect();
+        let takes: Vec<_> = recordings
+            .iter()
+            .zip(&names)
+            .map(|((_, model), name)| Take { name, model })
+            .collect();
+        let file_name = |path: &Path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let options = self.retargeted(ConvertOptions {
+            provenance: Some(Provenance {
+                generator: format!("Axon {}", env!("CARGO_PKG_VERSION")),
+                recordings: recordings.iter().map(|(path, _)| file_name(path)).collect(),
+                calibration: self.calibration.as_ref().map(|(_, path)| file_name(path)),
+            }),
+            ..self.convert_options.clone()
+        });
+        let output = match self.output_model(&options) {
+            Ok(output) => output,
+            Err(err) => return ErrorMessage::Occured(Arc::new(err)).task(),
+        };
+        match amcx_convert::to_gltf::convert_takes(
+            output.gltf,
             bin_name,
-            self.model.as_ref().unwrap(),
-            self.calibration.as_ref().map(|c| &c.0),
+            &takes,
+            &self.calibration(),
+            &self.mapping,
+            &options,
         ) {
-            Ok((new_gltf, bin)) => {
-                let mut bins = self.anim_model.bins.clone();
-                bins.push((bin_name.into(), bin));
+            Ok(conversion) => {
+                let angles = amcx_convert::angles::convert(
+                    &self.anim_model.gltf,
+                    self.model.as_ref().unwrap(),
+                    &self.calibration(),
+                    &self.mapping,
+                    &self.convert_options,
+                    &AngleOptions::default(),
+                );
+                let mut bins = output.bins;
+                bins.push((bin_name.into(), conversion.bin));
                 let converted = Converted {
                     modified: AnimModel {
-                        gltf: new_gltf,
+                        gltf: conversion.root,
                         bins,
                     },
+                    warnings: conversion.warnings,
+                    reduction: conversion.reduction,
+                    angles: angles.ok().map(|(angles, _)| angles),
                 };
                 self.converted = Some(converted);
                 ConvertingMessage::Dialog(ConvertingDialog::Save).task()
@@ -382,6 +947,14 @@ fn dialog(action: Action) -> impl Future<Output = Option<PathBuf>> {
     }
 }
 
+/// Takes are named after the file of their recording
+fn take_name(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("Animation")
+        .into()
+}
+
 fn read_file(path: &Path) -> impl Future<Output = Result<String, std::io::Error>> + use<'_> {
     tokio::fs::read_to_string(path)
 }
@@ -393,19 +966,88 @@ fn write_file<'a>(
     tokio::fs::write(path, content)
 }
 
-fn convert_dialog(action: ConvertingDialog) -> impl Future<Output = Option<PathBuf>> {
+fn convert_dialog(
+    action: ConvertingDialog,
+    format: ExportFormat,
+) -> impl Future<Output = Option<PathBuf>> {
     let mut dialog = rfd::AsyncFileDialog::new();
     dialog = match action {
         ConvertingDialog::Save => dialog
             .set_title("Select file to save to")
+            .add_filter(format.to_string(), &[format.extension()])
             .set_can_create_directories(true),
         ConvertingDialog::Calibration => dialog.set_title("Select calibration file"),
+        ConvertingDialog::OpenHinge => {
+            dialog.set_title("Select flexion recording of a hinge joint")
+        }
+        ConvertingDialog::OpenMapping => dialog.set_title("Select mapping file"),
+        ConvertingDialog::SaveMapping => dialog
+            .set_title("Select file to save mapping to")
+            .set_can_create_directories(true),
+        ConvertingDialog::OpenLimits => dialog.set_title("Select joint limits file"),
+        ConvertingDialog::OpenTake => dialog.set_title("Select recording to add as a take"),
+        ConvertingDialog::OpenTarget => dialog
+            .set_title("Select model to retarget onto")
+            .add_filter("glTF", &["gltf", "glb"]),
+        ConvertingDialog::OpenJointMap => dialog.set_title("Select joint map file"),
+        ConvertingDialog::OpenMeasurements => dialog.set_title("Select body measurements file"),
+        ConvertingDialog::SaveMotion(format) => dialog
+            .set_title(format!("Select file to save {format} to"))
+            .add_filter(format.to_string(), &[format.extension()])
+            .set_can_create_directories(true),
+        ConvertingDialog::SaveRom => dialog
+            .set_title("Select file to save range of motion to")
+            .add_filter("CSV", &["csv"])
+            .set_can_create_directories(true),
     };
     async move {
         match action {
-            ConvertingDialog::Save => dialog.save_file().await,
-            ConvertingDialog::Calibration => dialog.pick_file().await,
+            ConvertingDialog::Save
+            | ConvertingDialog::SaveMapping
+            | ConvertingDialog::SaveMotion(_)
+            | ConvertingDialog::SaveRom => dialog.save_file().await,
+            ConvertingDialog::Calibration
+            | ConvertingDialog::OpenHinge
+            | ConvertingDialog::OpenMapping
+            | ConvertingDialog::OpenLimits
+            | ConvertingDialog::OpenTake
+            | ConvertingDialog::OpenTarget
+            | ConvertingDialog::OpenJointMap
+            | ConvertingDialog::OpenMeasurements => dialog.pick_file().await,
         }
         .map(|fh| fh.path().to_path_buf())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "A A +y-x+z\nB B rpy:0,0,90\n";
+    const RECORDING: &str = "?[ BITS=16 ACCEL_SR=2 GYRO_SR=250 CLOCK=milli ]\n&[ A B ]\n@[ B=+x-z+y ]\n10\n[ 16384 0 0 0 0 0 ]\n[ 16384 0 0 0 0 0 ]\n";
+
+    fn open_mapping(state: &mut State) {
+        let _ = state.update(ConvertingMessage::MappingOpened(Arc::new(MAPPING.into())).into());
+    }
+
+    fn open_recording(state: &mut State) {
+        let path = PathBuf::from("Test.amcx");
+        let _ = state.update(FileMessage::Opened(Arc::new(RECORDING.into()), path).into());
+    }
+
+    #[test]
+    fn mountings_of_mapping_and_recording() {
+        let mapping_first: [fn(&mut State); 2] = [open_mapping, open_recording];
+        let recording_first: [fn(&mut State); 2] = [open_recording, open_mapping];
+        for order in [mapping_first, recording_first] {
+            let mut state = State::default();
+            let _ = state.update(Message::OpenTab(TabBar::Converter));
+            order.iter().for_each(|open| open(&mut state));
+            assert!(state.model.is_some());
+
+            // the mapping file mounts A, the header of the recording takes precedence for B
+            assert_eq!(state.mapping.mounting("A"), Some("+y-x+z".parse().unwrap()));
+            assert_eq!(state.mapping.mounting("B"), Some("+x-z+y".parse().unwrap()));
+        }
+    }
+}
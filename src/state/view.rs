@@ -1,3 +1,13 @@
+use std::fmt::Display;
+
+use amcx_convert::calibration::CalibrationMethod;
+use amcx_convert::export::ExportFormat;
+use amcx_convert::mapping::Coupling;
+use amcx_convert::smoothing::Filter;
+use amcx_convert::to_gltf::Rig;
+use amcx_convert::tracks::{FrameRate, KeyInterpolation, KeyReduction};
+use amcx_convert::world_frame::WorldFrame;
+
 use crate::default_models::DefaultModels;
 use crate::icons::Icon;
 
@@ -182,12 +192,26 @@ impl State {
             .into()
     }
     fn converting_actions(&self) -> Element<Message> {
-        button("Convert")
-            .on_press_maybe({
-                let if_active = self.model.is_some();
-                if_active.then_some(ConvertingMessage::Convert.into())
+        let convert = button("Convert").on_press_maybe({
+            let if_active = self.model.is_some();
+            if_active.then_some(ConvertingMessage::Convert.into())
+        });
+        let save_motion = |format: MotionFormat| {
+            button(text(format!("Save {format}"))).on_press_maybe({
+                let if_active = !self.dialog && self.model.is_some();
+                let dialog = ConvertingDialog::SaveMotion(format);
+                if_active.then_some(ConvertingMessage::Dialog(dialog).into())
             })
-            .into()
+        };
+        row![
+            convert,
+            save_motion(MotionFormat::Bvh),
+            save_motion(MotionFormat::Usd),
+            save_motion(MotionFormat::Angles),
+            save_motion(MotionFormat::AngleSummary)
+        ]
+        .spacing(5)
+        .into()
     }
     fn editor(&self) -> Element<Message> {
         let File { path, content, .. } = self.file.as_ref().unwrap();
@@ -245,22 +269,16 @@ impl State {
 
     fn converter(&self) -> Element<Message> {
         let joints: Vec<_> = self
-            .anim_model
-            .gltf
-            .skins
-            .iter()
-            .next()
-            .unwrap()
-            .joints
-            .iter()
-            .cloned()
+            .convert_options
+            .rig
+            .joints(&self.anim_model.gltf)
+            .unwrap_or_default()
+            .into_iter()
             .filter_map(|joint_idx| {
                 self.anim_model
                     .gltf
                     .get(joint_idx)
-                    .unwrap()
-                    .name
-                    .as_ref()
+                    .and_then(|node| node.name.as_ref())
                     .cloned()
             })
             .collect();
@@ -275,24 +293,88 @@ impl State {
             })
             .unwrap_or_default();
 
-        let sensors_view = sensors.iter().cloned().map(|sensor| {
-            let coupled = joints.contains(&sensor);
-            container(text(sensor))
+        let joint_choices: Vec<_> = [JointChoice::Ignored]
+            .into_iter()
+            .chain(joints.iter().cloned().map(JointChoice::Joint))
+            .collect();
+        let sensors_view = sensors.iter().map(|sensor| {
+            let coupling = self.mapping.get(sensor).cloned().or_else(|| {
+                joints.contains(sensor).then(|| Coupling::Joint {
+                    joint: sensor.clone(),
+                    mounting: None,
+                })
+            });
+            // None if ignored
+            let coupled = match &coupling {
+                Some(Coupling::Joint { joint, .. }) => Some(joints.contains(joint)),
+                Some(Coupling::Ignored) => None,
+                None => Some(false),
+            };
+            let (selected, mounting) = match &coupling {
+                Some(Coupling::Joint { joint, mounting }) => {
+                    (Some(JointChoice::Joint(joint.clone())), *mounting)
+                }
+                Some(Coupling::Ignored) => (Some(JointChoice::Ignored), None),
+                None => (None, None),
+            };
+
+            let joint_select = pick_list(joint_choices.clone(), selected, {
+                let sensor = sensor.clone();
+                move |choice| {
+                    let coupling = match choice {
+                        JointChoice::Ignored => Coupling::Ignored,
+                        JointChoice::Joint(joint) => Coupling::Joint { joint, mounting },
+                    };
+                    ConvertingMessage::Coupled(sensor.clone(), coupling).into()
+                }
+            })
+            .placeholder("Not coupled")
+            .width(Fill);
+
+            let label = container(text(sensor.clone()))
                 .center(Fill)
                 .style(move |theme| {
                     let mut default_style = bordered_box(theme);
                     let palette = theme.palette();
-                    default_style.text_color = Some(Color::BLACK);
-                    if coupled {
-                        default_style.background(palette.success)
-                    } else {
-                        default_style.background(palette.danger)
+                    match coupled {
+                        Some(true) => {
+                            default_style.text_color = Some(Color::BLACK);
+                            default_style.background(palette.success)
+                        }
+                        Some(false) => {
+                            default_style.text_color = Some(Color::BLACK);
+                            default_style.background(palette.danger)
+                        }
+                        None => default_style,
                     }
+                });
+            let mounting = self
+                .mounting_drafts
+                .get(sensor)
+                .cloned()
+                .unwrap_or_else(|| {
+                    mounting
+                        .map(|mounting| mounting.to_string())
+                        .unwrap_or_default()
+                });
+            let mounting_input = text_input("Mounting", &mounting)
+                .on_input({
+                    let sensor = sensor.clone();
+                    move |draft| ConvertingMessage::MountingEdited(sensor.clone(), draft).into()
                 })
+                .on_submit(ConvertingMessage::MountingSubmitted(sensor.clone()).into())
+                .width(Length::Fixed(140.0));
+
+            row![label, joint_select, mounting_input]
+                .spacing(5)
+                .height(Fill)
+                .align_y(Vertical::Center)
                 .into()
         });
         let joints_view = joints.iter().cloned().map(|joint| {
-            let coupled = sensors.contains(&joint);
+            let coupled = sensors
+                .iter()
+                .any(|sensor| self.mapping.joint(sensor) == Some(joint.as_str()));
             container(text(joint))
                 .center(Fill)
                 .style(move |theme| {
@@ -335,16 +417,260 @@ impl State {
                 None => "None",
             }
         });
-        let calibration_select =
-            row![calibration_select_button, calibration_select_status,].spacing(20);
+        let calibration_method = pick_list(
+            CalibrationMethod::ALL,
+            Some(self.calibration_method),
+            |method| ConvertingMessage::CalibrationMethodSelected(method).into(),
+        );
+        let hinge_open = button("Add Hinge").on_press_maybe({
+            let if_active = !self.dialog;
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenHinge).into())
+        });
+        let hinges_clear = button("Clear Hinges").on_press_maybe({
+            let if_active = !self.hinges.is_empty();
+            if_active.then_some(ConvertingMessage::ClearHinges.into())
+        });
+        let calibration_select = row![
+            calibration_select_button,
+            calibration_select_status,
+            calibration_method,
+            hinge_open,
+            hinges_clear
+        ]
+        .spacing(20)
+        .align_y(Vertical::Center);
+        let hinges = column(self.hinges.iter().enumerate().map(|(index, hinge)| {
+            let sensors: Vec<_> = hinge
+                .recording
+                .iter()
+                .map(|(sensor, _)| sensor.clone())
+                .collect();
+            row![
+                text(hinge.path.file_name().unwrap().to_str().unwrap()),
+                text("Parent"),
+                pick_list(sensors.clone(), Some(hinge.parent.clone()), move |sensor| {
+                    ConvertingMessage::HingeParentSelected(index, sensor).into()
+                }),
+                text("Child"),
+                pick_list(sensors, Some(hinge.child.clone()), move |sensor| {
+                    ConvertingMessage::HingeChildSelected(index, sensor).into()
+                }),
+                pick_list(HingeAxis::ALL, Some(hinge.axis), move |axis| {
+                    ConvertingMessage::HingeAxisSelected(index, axis).into()
+                })
+            ]
+            .spacing(5)
+            .align_y(Vertical::Center)
+            .into()
+        }))
+        .spacing(5);
+        let mapping_open = button("Load Mapping").on_press_maybe({
+            let if_active = !self.dialog;
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenMapping).into())
+        });
+        let mapping_save = button("Save Mapping").on_press_maybe({
+            let if_active = !self.dialog;
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::SaveMapping).into())
+        });
+        let take_open = button("Add Take").on_press_maybe({
+            let if_active = !self.dialog && self.model.is_some();
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenTake).into())
+        });
+        let takes_clear = button("Clear Takes").on_press_maybe({
+            let if_active = !self.takes.is_empty();
+            if_active.then_some(ConvertingMessage::ClearTakes.into())
+        });
+        let takes_status = text(match self.takes.len() {
+            0 => String::new(),
+            1 => "1 more take".into(),
+            count => format!("{count} more takes"),
+        });
+        let target_open = button("Retarget Onto").on_press_maybe({
+            let if_active = !self.dialog;
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenTarget).into())
+        });
+        let joint_map_open = button("Load Joint Map").on_press_maybe({
+            let if_active = !self.dialog && self.target.is_some();
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenJointMap).into())
+        });
+        let target_clear = button("Clear Target").on_press_maybe({
+            let if_active = self.target.is_some();
+            if_active.then_some(ConvertingMessage::ClearTarget.into())
+        });
+        let target_status = text(match self.target {
+            Some((ref path, _)) => path.file_name().unwrap().to_str().unwrap(),
+            None => "",
+        });
+        let measurements_open = button("Load Measurements").on_press_maybe({
+            let if_active = !self.dialog;
+            if_active
+                .then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenMeasurements).into())
+        });
+        let measurements_clear = button("Clear Measurements").on_press_maybe({
+            let if_active = !self.measurements.is_empty();
+            if_active.then_some(ConvertingMessage::ClearMeasurements.into())
+        });
+        let limits_open = button("Load Limits").on_press_maybe({
+            let if_active = !self.dialog;
+            if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::OpenLimits).into())
+        });
         let model_selector = pick_list(DefaultModels::ALL, Some(self.chosen_model), |s| {
             ConvertingMessage::ModelSelected(s).into()
         });
+        let rig_selector = pick_list(
+            Rig::all(&self.anim_model.gltf),
+            Some(self.convert_options.rig.clone()),
+            |rig| ConvertingMessage::RigSelected(rig).into(),
+        );
+        let world = pick_list(WorldFrame::ALL, Some(self.convert_options.world), |world| {
+            ConvertingMessage::WorldFrameSelected(world).into()
+        });
+        let export_format = pick_list(ExportFormat::ALL, Some(self.export_format), |format| {
+            ConvertingMessage::ExportFormatSelected(format).into()
+        });
+        let frame_rate = pick_list(
+            FrameRate::ALL,
+            Some(self.convert_options.frame_rate),
+            |frame_rate| ConvertingMessage::FrameRateSelected(frame_rate).into(),
+        );
+        let reduction = pick_list(
+            KeyReduction::ALL,
+            Some(self.convert_options.reduction),
+            |reduction| ConvertingMessage::KeyReductionSelected(reduction).into(),
+        );
+        let interpolation = pick_list(
+            KeyInterpolation::ALL,
+            Some(self.convert_options.interpolation),
+            |interpolation| ConvertingMessage::InterpolationSelected(interpolation).into(),
+        );
+        let filter = pick_list(
+            Filter::ALL,
+            Some(self.convert_options.smoothing.filter),
+            |filter| ConvertingMessage::FilterSelected(filter).into(),
+        );
+        let root_motion_choices: Vec<_> = [RootMotionChoice::Off]
+            .into_iter()
+            .chain(sensors.iter().cloned().map(RootMotionChoice::Sensor))
+            .collect();
+        let root_motion = pick_list(
+            root_motion_choices,
+            Some(match &self.convert_options.root_motion {
+                Some(root_motion) => RootMotionChoice::Sensor(root_motion.sensor.clone()),
+                None => RootMotionChoice::Off,
+            }),
+            |choice| {
+                let sensor = match choice {
+                    RootMotionChoice::Off => None,
+                    RootMotionChoice::Sensor(sensor) => Some(sensor),
+                };
+                ConvertingMessage::RootMotionSelected(sensor).into()
+            },
+        );
+        let skip_uncoupled = checkbox("Skip uncoupled", self.convert_options.skip_uncoupled)
+            .on_toggle(|skip| ConvertingMessage::SkipUncoupled(skip).into());
+        let heading = checkbox("Heading correction", self.convert_options.heading.is_some())
+            .on_toggle(|enabled| ConvertingMessage::HeadingCorrection(enabled).into());
 
-        container(column![
-            row![calibration_select, horizontal_space(), model_selector],
-            row![left.spacing(5), vertical_rule(2), right.spacing(5)].spacing(10)
-        ])
+        let warnings = self
+            .converted
+            .as_ref()
+            .filter(|converted| !converted.warnings.is_empty())
+            .map(|converted| {
+                let warnings = converted.warnings.iter().map(|warning| {
+                    container(text(warning.to_string()))
+                        .width(Fill)
+                        .padding(5)
+                        .style(bordered_box)
+                        .into()
+                });
+                column![text("Warnings").size(20), column(warnings).spacing(5)].spacing(5)
+            });
+        let reduction_report = self
+            .converted
+            .as_ref()
+            .and_then(|converted| converted.reduction.as_ref())
+            .map(|report| text(report.to_string()));
+        let rom = self
+            .converted
+            .as_ref()
+            .and_then(|converted| converted.angles.as_ref())
+            .map(|angles| {
+                let channels: Vec<_> = angles
+                    .joints
+                    .iter()
+                    .flat_map(|joint| {
+                        joint.axis_names().map(|axis| RomChannel {
+                            joint: joint.joint.clone(),
+                            axis,
+                        })
+                    })
+                    .collect();
+                let channel_select = pick_list(channels, self.rom_channel.clone(), |channel| {
+                    ConvertingMessage::RomChannelSelected(channel).into()
+                })
+                .placeholder("Joint angle");
+                let rom = self.rom();
+                let save = button("Save ROM").on_press_maybe({
+                    let if_active = !self.dialog && rom.is_some();
+                    if_active.then_some(ConvertingMessage::Dialog(ConvertingDialog::SaveRom).into())
+                });
+                let summary = text(rom.map_or(String::new(), |rom| rom.to_string()));
+                row![
+                    text("Range of motion"),
+                    channel_select,
+                    summary,
+                    horizontal_space(),
+                    save
+                ]
+                .spacing(5)
+                .align_y(Vertical::Center)
+            });
+
+        container(
+            column![
+                row![
+                    calibration_select,
+                    horizontal_space(),
+                    mapping_open,
+                    mapping_save,
+                    limits_open,
+                    measurements_open,
+                    measurements_clear,
+                    skip_uncoupled,
+                    heading,
+                    model_selector
+                ]
+                .spacing(5)
+                .align_y(Vertical::Center),
+                hinges,
+                row![
+                    text("Output"),
+                    take_open,
+                    takes_clear,
+                    takes_status,
+                    target_open,
+                    joint_map_open,
+                    target_clear,
+                    target_status,
+                    horizontal_space(),
+                    rig_selector,
+                    root_motion,
+                    filter,
+                    frame_rate,
+                    reduction,
+                    interpolation,
+                    world,
+                    export_format
+                ]
+                .spacing(5)
+                .align_y(Vertical::Center),
+                row![left.spacing(5), vertical_rule(2), right.spacing(5)].spacing(10)
+            ]
+            .push_maybe(reduction_report)
+            .push_maybe(rom)
+            .push_maybe(warnings)
+            .spacing(10),
+        )
         .height(Fill)
         .width(Fill)
         .style(bordered_box)
@@ -442,6 +768,34 @@ impl State {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JointChoice {
+    Ignored,
+    Joint(String),
+}
+impl Display for JointChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JointChoice::Ignored => "Ignored".fmt(f),
+            JointChoice::Joint(joint) => joint.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RootMotionChoice {
+    Off,
+    Sensor(String),
+}
+impl Display for RootMotionChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RootMotionChoice::Off => "No root motion".fmt(f),
+            RootMotionChoice::Sensor(sensor) => write!(f, "Root motion from {sensor}"),
+        }
+    }
+}
+
 fn mix(c1: Color, c2: Color, k: f32) -> Color {
     let f = 1.0 - k;
     Color {